@@ -0,0 +1,367 @@
+//! Per-pool configuration shared by the builder and the environment loader
+
+use crate::{DatabaseError, RetryPolicy, Role, SessionSettings, TlsConfig};
+use log::LevelFilter;
+use sqlx::{ConnectOptions, Postgres};
+use sqlx::pool::PoolOptions;
+use sqlx::postgres::PgConnectOptions;
+use std::path::{Path, PathBuf};
+use std::{env, fs, str::FromStr, time::Duration};
+
+/// Settings applied to a single connection pool
+///
+/// Any field left as `None` keeps the sqlx default.
+///
+/// # Example
+/// ```
+/// use database::PoolConfig;
+/// use std::time::Duration;
+///
+/// let config = PoolConfig {
+///     max_connections: Some(20),
+///     acquire_timeout: Some(Duration::from_secs(5)),
+///     ..PoolConfig::default()
+/// };
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct PoolConfig {
+    /// Maximum number of connections the pool may hold
+    pub max_connections: Option<u32>,
+    /// Minimum number of connections the pool tries to keep open
+    pub min_connections: Option<u32>,
+    /// Maximum time to wait for a connection before giving up
+    pub acquire_timeout: Option<Duration>,
+    /// Time after which an unused connection is closed
+    pub idle_timeout: Option<Duration>,
+    /// Maximum lifetime of a connection before it is replaced
+    pub max_lifetime: Option<Duration>,
+    /// Statements running longer than this are logged as warnings (sqlx defaults to 1 second)
+    ///
+    /// sqlx logs these without the pool's role. Use different thresholds per
+    /// role, or the per-role `application_name` in the server's own logs, to
+    /// tell writer and reader statements apart.
+    pub slow_query_threshold: Option<Duration>,
+    /// Acquires waiting longer than this are logged as warnings with the pool's role (defaults to 2 seconds)
+    ///
+    /// Covers connections checked out through
+    /// [`Database::acquire_writer`](crate::Database::acquire_writer) and
+    /// [`Database::acquire_reader`](crate::Database::acquire_reader), like the
+    /// acquire statistics. Waits of queries run directly against a pool are not logged.
+    pub slow_acquire_threshold: Option<Duration>,
+    /// File holding the password, overriding the one in the connection string
+    ///
+    /// Read when the pool is built and again for every new connection, so
+    /// rotated secrets are picked up without a [`reload`](crate::reload).
+    /// Ignored when the builder has a [`CredentialProvider`](crate::CredentialProvider).
+    pub password_file: Option<PathBuf>,
+    /// TLS mode and certificates
+    pub tls: TlsConfig,
+    /// Server settings applied to every new connection
+    pub session: SessionSettings,
+    /// Name the connections report in `pg_stat_activity`
+    ///
+    /// Used as is. Defaults to the binary name followed by the role, e.g.
+    /// `billing-api/writer`, unless the connection string or `PGAPPNAME` sets one.
+    pub application_name: Option<String>,
+    /// How connecting the pool is retried at startup
+    pub retry: RetryPolicy,
+}
+
+impl PoolConfig {
+    /// Read the pool configuration for a role from environment variables
+    ///
+    /// Each setting is looked up as `DATABASE_WRITE_<KEY>` or `DATABASE_READ_<KEY>`
+    /// first, falling back to the shared `DATABASE_<KEY>`.
+    ///
+    /// # Environment Variables
+    /// - MAX_CONNECTIONS: Maximum number of connections
+    /// - MIN_CONNECTIONS: Minimum number of connections
+    /// - ACQUIRE_TIMEOUT_MS: Acquire timeout in milliseconds
+    /// - IDLE_TIMEOUT_MS: Idle timeout in milliseconds
+    /// - MAX_LIFETIME_MS: Maximum connection lifetime in milliseconds
+    /// - SLOW_QUERY_MS: Slow statement logging threshold in milliseconds
+    /// - SLOW_ACQUIRE_MS: Slow acquire logging threshold in milliseconds
+    /// - PASSWORD_FILE: Path of a file holding the password
+    /// - SSL_*: TLS mode and certificates, see [`TlsConfig::from_env`]
+    /// - STATEMENT_TIMEOUT_MS, TIME_ZONE, ...: Session settings, see [`SessionSettings::from_env`]
+    /// - APPLICATION_NAME: Name reported in `pg_stat_activity`, followed by the role
+    ///   unless given per role
+    /// - RETRY_*: Startup retry policy, see [`RetryPolicy::from_env`]
+    ///
+    /// # Errors
+    /// [`DatabaseError::InvalidConfiguration`] if a variable cannot be parsed
+    pub fn from_env(role: Role) -> Result<Self, DatabaseError> {
+        Self::read(&Env::default(), role)
+    }
+
+    /// Read the pool configuration for a role from variables under the prefix of `env`
+    pub(crate) fn read(env: &Env, role: Role) -> Result<Self, DatabaseError> {
+        Ok(Self {
+            max_connections: env.parse_var(role, "MAX_CONNECTIONS")?,
+            min_connections: env.parse_var(role, "MIN_CONNECTIONS")?,
+            acquire_timeout: env.parse_var(role, "ACQUIRE_TIMEOUT_MS")?.map(Duration::from_millis),
+            idle_timeout: env.parse_var(role, "IDLE_TIMEOUT_MS")?.map(Duration::from_millis),
+            max_lifetime: env.parse_var(role, "MAX_LIFETIME_MS")?.map(Duration::from_millis),
+            slow_query_threshold: env.parse_var(role, "SLOW_QUERY_MS")?.map(Duration::from_millis),
+            slow_acquire_threshold: env.parse_var(role, "SLOW_ACQUIRE_MS")?.map(Duration::from_millis),
+            password_file: env.role_var(role, "PASSWORD_FILE").map(|(_, path)| PathBuf::from(path)),
+            tls: TlsConfig::read(env, role)?,
+            session: SessionSettings::read(env, role)?,
+            application_name: env.role_var(role, "APPLICATION_NAME").map(|(variable, name)| {
+                // The shared name is a base for both pools, so the role stays visible
+                if variable == env.name("APPLICATION_NAME") { format!("{name}/{role}") } else { name }
+            }),
+            retry: RetryPolicy::read(env, role)?,
+        })
+    }
+
+    /// Build sqlx pool options from this configuration
+    pub(crate) fn pool_options(&self) -> PoolOptions<Postgres> {
+        let mut options = PoolOptions::new();
+
+        if let Some(max_connections) = self.max_connections {
+            options = options.max_connections(max_connections);
+        }
+
+        if let Some(min_connections) = self.min_connections {
+            options = options.min_connections(min_connections);
+        }
+
+        if let Some(acquire_timeout) = self.acquire_timeout {
+            options = options.acquire_timeout(acquire_timeout);
+        }
+
+        if let Some(idle_timeout) = self.idle_timeout {
+            options = options.idle_timeout(idle_timeout);
+        }
+
+        if let Some(max_lifetime) = self.max_lifetime {
+            options = options.max_lifetime(max_lifetime);
+        }
+
+        // Slow acquires are logged with the pool's role by `PoolCounters::acquire` instead
+        options.acquire_slow_level(LevelFilter::Off)
+    }
+
+    /// Apply the per-connection parts of this configuration to the connect options
+    ///
+    /// sqlx logs slow statements with their SQL text only, so bound parameters never appear in the log.
+    pub(crate) fn connect_options(&self, role: Role, mut options: PgConnectOptions) -> PgConnectOptions {
+        if let Some(threshold) = self.slow_query_threshold {
+            options = options.log_slow_statements(LevelFilter::Warn, threshold);
+        }
+
+        match &self.application_name {
+            Some(name) => options.application_name(name),
+            None if options.get_application_name().is_none() => {
+                options.application_name(&format!("{}/{role}", binary_name()))
+            }
+            None => options,
+        }
+    }
+}
+
+/// Name of the running executable, without directory or extension
+fn binary_name() -> String {
+    env::current_exe()
+        .ok()
+        .and_then(|path| path.file_stem().map(|name| name.to_string_lossy().into_owned()))
+        .unwrap_or_else(|| "unknown".to_string())
+}
+
+/// Environment variables sharing a common prefix
+///
+/// The prefix is `DATABASE` for the global instance and e.g. `ANALYTICS_DATABASE`
+/// for the [named](crate::init_named) instance `analytics`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct Env {
+    prefix: String,
+}
+
+impl Default for Env {
+    fn default() -> Self {
+        Self { prefix: "DATABASE".to_string() }
+    }
+}
+
+impl Env {
+    /// Variables of a named instance, e.g. `ANALYTICS_DATABASE_URL` for `analytics`
+    pub fn named(name: &str) -> Self {
+        let name = name.to_ascii_uppercase().replace(['-', '.', ' '], "_");
+        Self { prefix: format!("{name}_DATABASE") }
+    }
+
+    /// Full name of a variable, e.g. `DATABASE_URL` for `URL`
+    pub fn name(&self, key: &str) -> String {
+        format!("{}_{key}", self.prefix)
+    }
+
+    /// Look up a variable
+    pub fn var(&self, key: &str) -> Option<String> {
+        env::var(self.name(key)).ok()
+    }
+
+    /// Look up a role-specific variable, falling back to the shared one
+    ///
+    /// # Returns
+    /// The name of the variable that was found together with its value
+    pub fn role_var(&self, role: Role, key: &str) -> Option<(String, String)> {
+        let infix = match role {
+            Role::Writer => "WRITE",
+            Role::Reader => "READ",
+        };
+
+        [self.name(&format!("{infix}_{key}")), self.name(key)]
+            .into_iter()
+            .find_map(|variable| env::var(&variable).ok().map(|value| (variable, value)))
+    }
+
+    /// Look up a variable, or read it from the file named by its `_FILE` variant
+    ///
+    /// `DATABASE_URL` takes precedence over `DATABASE_URL_FILE`, for example.
+    pub fn var_or_file(&self, role: Role, key: &str) -> Result<Option<String>, DatabaseError> {
+        if let Some(value) = self.var(key) {
+            return Ok(Some(value));
+        }
+
+        match env::var_os(self.name(&format!("{key}_FILE"))) {
+            Some(path) => read_file(role, Path::new(&path)).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Look up a boolean variable, accepting `true`/`false`, `1`/`0` and `yes`/`no`
+    pub fn parse_flag(&self, key: &str) -> Result<Option<bool>, DatabaseError> {
+        match self.var(key) {
+            Some(value) => flag(Role::Writer, self.name(key), value).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Look up and parse a variable that applies to the whole database rather than one role
+    pub fn parse<T: FromStr>(&self, key: &str) -> Result<Option<T>, DatabaseError> {
+        let Some(value) = self.var(key) else {
+            return Ok(None);
+        };
+
+        match value.trim().parse() {
+            Ok(parsed) => Ok(Some(parsed)),
+            Err(_) => Err(DatabaseError::InvalidConfiguration { role: Role::Writer, variable: self.name(key), value }),
+        }
+    }
+
+    /// Look up a role-specific boolean variable, falling back to the shared one
+    ///
+    /// Accepts the same values as [`Env::parse_flag`].
+    pub fn parse_role_flag(&self, role: Role, key: &str) -> Result<Option<bool>, DatabaseError> {
+        match self.role_var(role, key) {
+            Some((variable, value)) => flag(role, variable, value).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Look up and parse a role-specific variable
+    pub fn parse_var<T: FromStr>(&self, role: Role, key: &str) -> Result<Option<T>, DatabaseError> {
+        let Some((variable, value)) = self.role_var(role, key) else {
+            return Ok(None);
+        };
+
+        match value.trim().parse() {
+            Ok(parsed) => Ok(Some(parsed)),
+            Err(_) => Err(DatabaseError::InvalidConfiguration { role, variable, value }),
+        }
+    }
+}
+
+/// Parse a boolean value, accepting `true`/`false`, `1`/`0` and `yes`/`no`
+fn flag(role: Role, variable: String, value: String) -> Result<bool, DatabaseError> {
+    match value.trim().to_ascii_lowercase().as_str() {
+        "true" | "1" | "yes" => Ok(true),
+        "false" | "0" | "no" => Ok(false),
+        _ => Err(DatabaseError::InvalidConfiguration { role, variable, value }),
+    }
+}
+
+/// Read a secret from a file, dropping the trailing newline most tools write
+pub(crate) fn read_file(role: Role, path: &Path) -> Result<String, DatabaseError> {
+    match fs::read_to_string(path) {
+        Ok(contents) => Ok(contents.trim_end_matches(['\r', '\n']).to_string()),
+        Err(source) => Err(DatabaseError::UnreadableFile { role, path: path.to_path_buf(), source }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Application name the connections of `role` report, reading variables under `prefix`
+    fn application_name(prefix: &str, role: Role) -> Option<String> {
+        let config = PoolConfig::read(&Env::named(prefix), role).unwrap();
+        let options = config.connect_options(role, PgConnectOptions::new_without_pgpass());
+
+        options.get_application_name().map(str::to_string)
+    }
+
+    #[test]
+    fn application_name_defaults_to_binary_and_role() {
+        let writer = application_name("application_name_default", Role::Writer);
+
+        assert_eq!(writer, Some(format!("{}/writer", binary_name())));
+        assert_eq!(application_name("application_name_default", Role::Reader), Some(format!("{}/reader", binary_name())));
+    }
+
+    #[test]
+    fn shared_application_name_keeps_the_role() {
+        // SAFETY: the variable is unique to this test and no other thread reads it
+        unsafe { env::set_var("APPLICATION_NAME_SHARED_DATABASE_APPLICATION_NAME", "billing") };
+
+        assert_eq!(application_name("application_name_shared", Role::Writer).as_deref(), Some("billing/writer"));
+        assert_eq!(application_name("application_name_shared", Role::Reader).as_deref(), Some("billing/reader"));
+    }
+
+    #[test]
+    fn per_role_application_name_is_used_verbatim() {
+        // SAFETY: the variables are unique to this test and no other thread reads them
+        unsafe {
+            env::set_var("APPLICATION_NAME_ROLE_DATABASE_APPLICATION_NAME", "billing");
+            env::set_var("APPLICATION_NAME_ROLE_DATABASE_READ_APPLICATION_NAME", "billing-reports");
+        }
+
+        assert_eq!(application_name("application_name_role", Role::Writer).as_deref(), Some("billing/writer"));
+        assert_eq!(application_name("application_name_role", Role::Reader).as_deref(), Some("billing-reports"));
+    }
+
+    #[test]
+    fn explicit_application_name_is_used_verbatim() {
+        let config = PoolConfig { application_name: Some("worker".to_string()), ..PoolConfig::default() };
+        let options = config.connect_options(Role::Reader, PgConnectOptions::new_without_pgpass());
+
+        assert_eq!(options.get_application_name(), Some("worker"));
+    }
+
+    #[test]
+    fn connection_string_application_name_is_kept() {
+        let options = "postgres://db/app?application_name=psql-session".parse::<PgConnectOptions>().unwrap();
+        let options = PoolConfig::default().connect_options(Role::Writer, options);
+
+        assert_eq!(options.get_application_name(), Some("psql-session"));
+    }
+
+    #[test]
+    fn database_wide_variables_ignore_role_names() {
+        // SAFETY: the variables are unique to this test and no other thread reads them
+        unsafe {
+            env::set_var("PARSE_SHARED_DATABASE_MAX_REPLICA_LAG_MS", " 250 ");
+            env::set_var("PARSE_ROLE_DATABASE_READ_MAX_REPLICA_LAG_MS", "250");
+            env::set_var("PARSE_INVALID_DATABASE_MAX_REPLICA_LAG_MS", "soon");
+        }
+
+        assert_eq!(Env::named("parse_shared").parse::<u64>("MAX_REPLICA_LAG_MS").unwrap(), Some(250));
+        assert_eq!(Env::named("parse_role").parse::<u64>("MAX_REPLICA_LAG_MS").unwrap(), None);
+        assert!(matches!(
+            Env::named("parse_invalid").parse::<u64>("MAX_REPLICA_LAG_MS"),
+            Err(DatabaseError::InvalidConfiguration { variable, value, .. })
+                if variable == "PARSE_INVALID_DATABASE_MAX_REPLICA_LAG_MS" && value == "soon"
+        ));
+    }
+}
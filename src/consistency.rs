@@ -0,0 +1,109 @@
+//! Read-your-writes consistency tokens based on WAL positions
+
+use sqlx::{Pool, Postgres};
+use std::{fmt, str::FromStr, time::Duration};
+
+/// Interval between replay position checks while waiting for a replica to catch up
+pub(crate) const POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+/// Position in the writer's write-ahead log captured after a write
+///
+/// Pass the token to [`Database::reader_after`](crate::Database::reader_after)
+/// to get a reader that has replayed at least up to this position. Tokens format
+/// as Postgres LSNs (e.g. `16/B374D848`) and parse back with [`FromStr`], so they
+/// can be handed to clients and returned with their next request.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ConsistencyToken(pub(crate) u64);
+
+impl ConsistencyToken {
+    /// Capture the writer's current WAL position
+    ///
+    /// Call this after the write has been committed.
+    pub(crate) async fn capture(writer: &Pool<Postgres>) -> Result<Self, sqlx::Error> {
+        let lsn = sqlx::query_scalar::<_, String>("SELECT pg_current_wal_lsn()::text")
+            .fetch_one(writer)
+            .await?;
+
+        lsn.parse().map_err(|error: String| sqlx::Error::Decode(error.into()))
+    }
+}
+
+/// WAL position a server has replayed (replica) or written (primary) up to
+const POSITION_QUERY: &str = "
+    SELECT CASE
+        WHEN pg_is_in_recovery() THEN pg_last_wal_replay_lsn()
+        ELSE pg_current_wal_lsn()
+    END::text
+";
+
+/// Fetch the WAL position a replica has replayed up to
+///
+/// # Returns
+/// `None` if the replica did not answer within `timeout` or reported no position
+pub(crate) async fn position(pool: &Pool<Postgres>, timeout: Duration) -> Option<ConsistencyToken> {
+    let query = sqlx::query_scalar::<_, Option<String>>(POSITION_QUERY).fetch_one(pool);
+
+    match tokio::time::timeout(timeout, query).await {
+        Ok(Ok(Some(position))) => position.parse().ok(),
+        _ => None,
+    }
+}
+
+impl fmt::Display for ConsistencyToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:X}/{:X}", self.0 >> 32, self.0 & u64::from(u32::MAX))
+    }
+}
+
+impl FromStr for ConsistencyToken {
+    type Err = String;
+
+    fn from_str(string: &str) -> Result<Self, Self::Err> {
+        let parse = |part: &str| u32::from_str_radix(part, 16).ok();
+
+        match string.trim().split_once('/') {
+            Some((high, low)) => match (parse(high), parse(low)) {
+                (Some(high), Some(low)) => Ok(Self(u64::from(high) << 32 | u64::from(low))),
+                _ => Err(format!("invalid log sequence number: {string}")),
+            },
+            None => Err(format!("invalid log sequence number: {string}")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips_log_sequence_numbers() {
+        for lsn in ["16/B374D848", "0/0", "0/1", "FFFFFFFF/FFFFFFFF"] {
+            let token: ConsistencyToken = lsn.parse().unwrap();
+            assert_eq!(token.to_string(), lsn);
+        }
+    }
+
+    #[test]
+    fn parses_into_ordered_positions() {
+        let token: ConsistencyToken = "16/B374D848".parse().unwrap();
+
+        assert_eq!(token, ConsistencyToken(0x16_B374_D848));
+        assert!(token > "16/B374D847".parse().unwrap());
+        assert!(token < "17/0".parse().unwrap());
+    }
+
+    #[test]
+    fn accepts_lowercase_and_surrounding_whitespace() {
+        let token: ConsistencyToken = " 16/b374d848\n".parse().unwrap();
+
+        assert_eq!(token.to_string(), "16/B374D848");
+    }
+
+    #[test]
+    fn rejects_malformed_input() {
+        for lsn in ["", "16", "16/", "/B374D848", "16/B374D848/0", "G/0", "16-B374D848", "100000000/0"] {
+            let error = lsn.parse::<ConsistencyToken>().unwrap_err();
+            assert_eq!(error, format!("invalid log sequence number: {lsn}"));
+        }
+    }
+}
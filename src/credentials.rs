@@ -0,0 +1,364 @@
+//! Credentials fetched for every new connection, for short-lived tokens and rotated secrets
+
+use crate::replica::Replicas;
+use crate::stats::PoolCounters;
+use crate::{DatabaseError, Role};
+use sqlx::postgres::PgConnectOptions;
+use sqlx::{Pool, Postgres};
+use std::error::Error;
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::Duration;
+use std::{fmt, io};
+use tokio::process::Command;
+use tokio::task::AbortHandle;
+
+/// Longest time credentials are kept before being fetched again, even without new connections
+const REFRESH_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Default time a credential command may run before it is killed
+const DEFAULT_COMMAND_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Error returned by a [`CredentialProvider`]
+pub type CredentialError = Box<dyn Error + Send + Sync>;
+
+/// Future returned by [`CredentialProvider::credentials`]
+pub type CredentialFuture<'a> = Pin<Box<dyn Future<Output = Result<Credentials, CredentialError>> + Send + 'a>>;
+
+/// Username and password used to open a connection
+#[derive(Clone, PartialEq, Eq)]
+pub struct Credentials {
+    /// User to connect as, or `None` to keep the user from the connection string
+    pub username: Option<String>,
+    /// Password or token to authenticate with
+    pub password: String,
+}
+
+impl Credentials {
+    /// Credentials that only replace the password
+    pub fn password(password: impl Into<String>) -> Self {
+        Self { username: None, password: password.into() }
+    }
+}
+
+impl fmt::Debug for Credentials {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Credentials")
+            .field("username", &self.username)
+            .field("password", &"***")
+            .finish()
+    }
+}
+
+/// Source of credentials that are fetched again whenever a new connection opens
+///
+/// Use this for IAM authentication tokens, Vault dynamic secrets and other
+/// short-lived credentials instead of baking a static password into the URL.
+/// sqlx offers no hook that runs right before connecting, so credentials are
+/// fetched when the pool is built, again right after each new connection opens
+/// for the next one or is rejected by the server, and at least once a minute.
+/// Providers backed by a remote service should cache credentials that are still
+/// valid.
+///
+/// # Example
+/// ```
+/// use database::{CredentialFuture, CredentialProvider, Credentials, Role};
+///
+/// struct StaticToken(String);
+///
+/// impl CredentialProvider for StaticToken {
+///     fn credentials(&self, _role: Role) -> CredentialFuture<'_> {
+///         Box::pin(async move { Ok(Credentials::password(self.0.clone())) })
+///     }
+/// }
+/// ```
+pub trait CredentialProvider: Send + Sync + 'static {
+    /// Fetch the credentials for a new connection of the given role
+    fn credentials(&self, role: Role) -> CredentialFuture<'_>;
+}
+
+/// Reads the password, and optionally the username, from files
+///
+/// The files are read again for every new connection, so secrets rotated by
+/// Docker or Kubernetes take effect without reloading. A trailing newline is ignored.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FileCredentials {
+    password: PathBuf,
+    username: Option<PathBuf>,
+}
+
+impl FileCredentials {
+    /// Read the password from `path`
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { password: path.into(), username: None }
+    }
+
+    /// Also read the username from `path`
+    pub fn username_file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.username = Some(path.into());
+        self
+    }
+}
+
+impl CredentialProvider for FileCredentials {
+    fn credentials(&self, _role: Role) -> CredentialFuture<'_> {
+        Box::pin(async move {
+            let password = read(&self.password).await?;
+            let username = match &self.username {
+                Some(path) => Some(read(path).await?),
+                None => None,
+            };
+
+            Ok(Credentials { username, password })
+        })
+    }
+}
+
+/// Read a secret from a file, naming the file in the error
+async fn read(path: &Path) -> Result<String, CredentialError> {
+    match tokio::fs::read_to_string(path).await {
+        Ok(contents) => Ok(contents.trim_end_matches(['\r', '\n']).to_string()),
+        Err(error) => Err(format!("unable to read {}: {error}", path.display()).into()),
+    }
+}
+
+/// Runs a command and uses what it prints as the password
+///
+/// Suited for CLIs that mint tokens, e.g. `aws rds generate-db-auth-token`.
+/// The command runs without a shell and is started again for every new
+/// connection. Surrounding whitespace in its output is ignored. A command still
+/// running after the [timeout](CommandCredentials::timeout) is killed.
+///
+/// # Example
+/// ```
+/// use database::CommandCredentials;
+///
+/// let provider = CommandCredentials::new("aws").args([
+///     "rds", "generate-db-auth-token",
+///     "--hostname", "primary-db.example.com",
+///     "--port", "5432",
+///     "--username", "app",
+/// ]);
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CommandCredentials {
+    program: String,
+    args: Vec<String>,
+    timeout: Duration,
+}
+
+impl CommandCredentials {
+    /// Run `program` to obtain the password
+    pub fn new(program: impl Into<String>) -> Self {
+        Self { program: program.into(), args: Vec::new(), timeout: DEFAULT_COMMAND_TIMEOUT }
+    }
+
+    /// Pass arguments to the program
+    pub fn args<I>(mut self, args: I) -> Self
+    where
+        I: IntoIterator,
+        I::Item: Into<String>,
+    {
+        self.args.extend(args.into_iter().map(Into::into));
+        self
+    }
+
+    /// Kill the program and fail if it runs longer than `timeout`. Defaults to 30 seconds.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+}
+
+impl CredentialProvider for CommandCredentials {
+    fn credentials(&self, _role: Role) -> CredentialFuture<'_> {
+        Box::pin(async move {
+            let output = Command::new(&self.program).args(&self.args).kill_on_drop(true).output();
+            let output = tokio::time::timeout(self.timeout, output)
+                .await
+                .map_err(|_| format!("{} did not finish within {:?}", self.program, self.timeout))?
+                .map_err(|error| format!("unable to run {}: {error}", self.program))?;
+
+            if !output.status.success() {
+                let stderr = String::from_utf8_lossy(&output.stderr);
+                return Err(format!("{} failed with {}: {}", self.program, output.status, stderr.trim()).into());
+            }
+
+            match String::from_utf8(output.stdout) {
+                Ok(stdout) => Ok(Credentials::password(stdout.trim())),
+                Err(_) => Err(io::Error::new(io::ErrorKind::InvalidData, "credential command printed invalid UTF-8").into()),
+            }
+        })
+    }
+}
+
+/// Fetch credentials for a role and apply them to the connect options
+async fn apply(
+    provider: &dyn CredentialProvider,
+    role: Role,
+    options: PgConnectOptions,
+) -> Result<PgConnectOptions, DatabaseError> {
+    let credentials = provider.credentials(role)
+        .await
+        .map_err(|source| DatabaseError::Credentials { role, source })?;
+
+    let options = match &credentials.username {
+        Some(username) => options.username(username),
+        None => options,
+    };
+
+    Ok(options.password(&credentials.password))
+}
+
+/// Credential refresh state of a single pool
+pub(crate) struct Refresh {
+    provider: Arc<dyn CredentialProvider>,
+    counters: Arc<PoolCounters>,
+    /// Connect options without fetched credentials applied
+    options: PgConnectOptions,
+}
+
+impl Refresh {
+    /// Fetch the first credentials of a pool
+    ///
+    /// # Returns
+    /// The refresh state and the connect options to open the pool with
+    pub async fn new(
+        provider: Arc<dyn CredentialProvider>,
+        counters: &Arc<PoolCounters>,
+        options: PgConnectOptions,
+    ) -> Result<(Self, PgConnectOptions), DatabaseError> {
+        let authenticated = apply(provider.as_ref(), counters.role, options.clone()).await?;
+        let refresh = Self { provider, counters: Arc::clone(counters), options };
+
+        Ok((refresh, authenticated))
+    }
+
+    /// Refresh state of a pool whose first credentials are not fetched up front
+    ///
+    /// Used for lazy pools and for replicas that could not connect.
+    /// The credentials are fetched as soon as the refresh task starts.
+    pub fn deferred(provider: Arc<dyn CredentialProvider>, counters: &Arc<PoolCounters>, options: PgConnectOptions) -> Self {
+        counters.refresh.notify_one();
+
+        Self { provider, counters: Arc::clone(counters), options }
+    }
+
+    /// Spawn a task that keeps the credentials of `pool` fresh
+    ///
+    /// New credentials are fetched whenever a connection opens or is rejected
+    /// by the server, and at least every [`REFRESH_INTERVAL`]. Failures are
+    /// logged and the previous credentials stay in use. Like the health checker,
+    /// the task stops once the replicas are dropped or when aborted through the
+    /// returned handle.
+    pub fn spawn(self, pool: Pool<Postgres>, replicas: &Arc<Replicas>) -> AbortHandle {
+        let replicas = Arc::downgrade(replicas);
+        let role = self.counters.role;
+
+        let task = tokio::spawn(async move {
+            loop {
+                let _ = tokio::time::timeout(REFRESH_INTERVAL, self.counters.refresh.notified()).await;
+
+                if replicas.strong_count() == 0 {
+                    break;
+                }
+
+                match apply(self.provider.as_ref(), role, self.options.clone()).await {
+                    Ok(options) => pool.set_connect_options(options),
+                    Err(error) => log::warn!("Keeping the previous {role} database credentials: {error}"),
+                }
+            }
+        });
+
+        task.abort_handle()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    /// Path of a scratch file unique to this process and test
+    fn scratch(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("database-credentials-{}-{name}", std::process::id()))
+    }
+
+    #[tokio::test]
+    async fn file_credentials_trim_trailing_newlines() {
+        let password = scratch("password");
+        let username = scratch("username");
+        fs::write(&password, "s3cret \r\n").unwrap();
+        fs::write(&username, "app\n").unwrap();
+
+        let credentials = FileCredentials::new(&password).username_file(&username).credentials(Role::Writer).await;
+        let _ = fs::remove_file(&password);
+        let _ = fs::remove_file(&username);
+
+        let credentials = credentials.unwrap();
+        assert_eq!(credentials.username.as_deref(), Some("app"));
+        assert_eq!(credentials.password, "s3cret ");
+    }
+
+    #[tokio::test]
+    async fn file_credentials_report_missing_file() {
+        let path = scratch("missing");
+        let error = FileCredentials::new(&path).credentials(Role::Reader).await.unwrap_err();
+
+        assert!(error.to_string().contains(&path.display().to_string()));
+    }
+
+    #[tokio::test]
+    async fn command_credentials_use_output_as_password() {
+        let credentials = CommandCredentials::new("echo").args(["token"]).credentials(Role::Writer).await.unwrap();
+
+        assert_eq!(credentials, Credentials::password("token"));
+    }
+
+    #[tokio::test]
+    async fn command_credentials_report_failure() {
+        let error = CommandCredentials::new("sh")
+            .args(["-c", "echo denied >&2; exit 3"])
+            .credentials(Role::Writer)
+            .await
+            .unwrap_err()
+            .to_string();
+
+        assert!(error.starts_with("sh failed with exit status: 3"), "{error}");
+        assert!(error.ends_with("denied"), "{error}");
+    }
+
+    #[tokio::test]
+    async fn command_credentials_time_out() {
+        let error = CommandCredentials::new("sleep")
+            .args(["5"])
+            .timeout(Duration::from_millis(50))
+            .credentials(Role::Writer)
+            .await
+            .unwrap_err();
+
+        assert_eq!(error.to_string(), "sleep did not finish within 50ms");
+    }
+
+    #[tokio::test]
+    async fn command_credentials_reject_invalid_utf8() {
+        let error = CommandCredentials::new("sh")
+            .args(["-c", r"printf '\377'"])
+            .credentials(Role::Writer)
+            .await
+            .unwrap_err();
+
+        assert_eq!(error.downcast_ref::<io::Error>().map(io::Error::kind), Some(io::ErrorKind::InvalidData));
+    }
+
+    #[test]
+    fn debug_masks_password() {
+        let credentials = Credentials { username: Some("app".to_string()), password: "s3cret".to_string() };
+        let debug = format!("{credentials:?}");
+
+        assert!(!debug.contains("s3cret"));
+        assert_eq!(debug, r#"Credentials { username: Some("app"), password: "***" }"#);
+    }
+}
@@ -0,0 +1,169 @@
+//! Error types returned by the fallible database API
+
+use std::{error, fmt, io, path::PathBuf};
+
+/// Role a connection pool plays in the read/write split
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize), serde(rename_all = "lowercase"))]
+pub enum Role {
+    /// Pool used for write operations
+    Writer,
+    /// Pool used for read operations
+    Reader,
+}
+
+impl Role {
+    /// Lowercase name of the role, suitable for logs and labels
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Writer => "writer",
+            Self::Reader => "reader",
+        }
+    }
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Errors that can occur while establishing the database connection pools
+///
+/// Every variant carries the [`Role`] of the pool that failed so callers can
+/// tell a broken replica apart from a broken primary.
+#[derive(Debug)]
+pub enum DatabaseError {
+    /// No connection string was provided for the role
+    MissingConfiguration {
+        role: Role,
+        /// Environment variable(s) that were consulted
+        variable: String,
+    },
+    /// A configuration value could not be parsed
+    InvalidConfiguration {
+        role: Role,
+        /// Environment variable holding the invalid value
+        variable: String,
+        value: String,
+    },
+    /// A file holding a connection string or password could not be read
+    UnreadableFile {
+        role: Role,
+        path: PathBuf,
+        source: io::Error,
+    },
+    /// A [`CredentialProvider`](crate::CredentialProvider) failed to supply credentials
+    Credentials {
+        role: Role,
+        source: Box<dyn error::Error + Send + Sync>,
+    },
+    /// A TLS certificate or key could not be loaded
+    Certificate {
+        role: Role,
+        path: PathBuf,
+        source: io::Error,
+    },
+    /// The connection string could not be parsed
+    InvalidUrl { role: Role, source: sqlx::Error },
+    /// The server rejected the supplied credentials
+    Authentication { role: Role, source: sqlx::Error },
+    /// The server could not be reached over the network
+    Unreachable { role: Role, source: sqlx::Error },
+    /// The connection attempt did not complete in time
+    Timeout { role: Role },
+    /// The session settings were rejected by the server
+    Session { role: Role, source: sqlx::Error },
+    /// The TLS handshake failed, e.g. because the server certificate could not be verified
+    Tls { role: Role, source: sqlx::Error },
+    /// Any other failure reported while connecting
+    Connection { role: Role, source: sqlx::Error },
+}
+
+impl DatabaseError {
+    /// Classify a connection error reported by sqlx for the given role
+    pub(crate) fn from_sqlx(role: Role, source: sqlx::Error) -> Self {
+        match &source {
+            sqlx::Error::Configuration(_) => Self::InvalidUrl { role, source },
+            _ if is_authentication(&source) => Self::Authentication { role, source },
+            sqlx::Error::Tls(_) => Self::Tls { role, source },
+            // rustls reports certificate verification failures as invalid data
+            sqlx::Error::Io(error) if error.kind() == io::ErrorKind::InvalidData => Self::Tls { role, source },
+            sqlx::Error::PoolTimedOut => Self::Timeout { role },
+            sqlx::Error::Io(error) if error.kind() == io::ErrorKind::TimedOut => Self::Timeout { role },
+            sqlx::Error::Io(_) => Self::Unreachable { role, source },
+            _ => Self::Connection { role, source },
+        }
+    }
+
+    /// Role of the pool that failed
+    pub fn role(&self) -> Role {
+        match self {
+            Self::MissingConfiguration { role, .. }
+            | Self::InvalidConfiguration { role, .. }
+            | Self::UnreadableFile { role, .. }
+            | Self::Credentials { role, .. }
+            | Self::Certificate { role, .. }
+            | Self::InvalidUrl { role, .. }
+            | Self::Authentication { role, .. }
+            | Self::Unreachable { role, .. }
+            | Self::Timeout { role }
+            | Self::Session { role, .. }
+            | Self::Tls { role, .. }
+            | Self::Connection { role, .. } => *role,
+        }
+    }
+}
+
+/// Whether the server rejected the credentials of a connection
+pub(crate) fn is_authentication(error: &sqlx::Error) -> bool {
+    // SQLSTATE class 28 covers invalid authorization specifications
+    matches!(error, sqlx::Error::Database(error) if error.code().is_some_and(|code| code.starts_with("28")))
+}
+
+impl fmt::Display for DatabaseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MissingConfiguration { role, variable } => {
+                write!(f, "missing {role} database configuration: set {variable}")
+            }
+            Self::InvalidConfiguration { role, variable, value } => {
+                write!(f, "invalid {role} database configuration: {variable}={value:?}")
+            }
+            Self::UnreadableFile { role, path, source } => {
+                write!(f, "unable to read {role} database file {}: {source}", path.display())
+            }
+            Self::Credentials { role, source } => write!(f, "unable to fetch {role} database credentials: {source}"),
+            Self::Certificate { role, path, source } => {
+                write!(f, "unable to load {role} database certificate {}: {source}", path.display())
+            }
+            Self::InvalidUrl { role, source } => write!(f, "invalid {role} database url: {source}"),
+            Self::Authentication { role, source } => {
+                write!(f, "{role} database authentication failed: {source}")
+            }
+            Self::Unreachable { role, source } => write!(f, "{role} database is unreachable: {source}"),
+            Self::Timeout { role } => write!(f, "timed out connecting to the {role} database"),
+            Self::Session { role, source } => write!(f, "unable to apply {role} database session settings: {source}"),
+            Self::Tls { role, source } => write!(f, "TLS with the {role} database failed: {source}"),
+            Self::Connection { role, source } => write!(f, "unable to connect to the {role} database: {source}"),
+        }
+    }
+}
+
+impl error::Error for DatabaseError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Self::UnreadableFile { source, .. } | Self::Certificate { source, .. } => Some(source),
+            Self::Credentials { source, .. } => Some(source.as_ref()),
+            Self::InvalidUrl { source, .. }
+            | Self::Authentication { source, .. }
+            | Self::Unreachable { source, .. }
+            | Self::Session { source, .. }
+            | Self::Tls { source, .. }
+            | Self::Connection { source, .. } => Some(source),
+            Self::MissingConfiguration { .. }
+            | Self::InvalidConfiguration { .. }
+            | Self::Timeout { .. } => None,
+        }
+    }
+}
@@ -0,0 +1,236 @@
+//! Background health checking of read replicas and on-demand health reports
+
+use crate::replica::Replicas;
+use crate::spans::{self, Operation};
+use crate::stats::PoolCounters;
+use crate::{ConsistencyToken, Role};
+use sqlx::{ConnectOptions, Connection, PgConnection, Pool, Postgres};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::task::AbortHandle;
+use tokio::time::{Instant, MissedTickBehavior};
+
+/// Default time between replica health checks
+pub(crate) const DEFAULT_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Default time a replica has to answer a health check
+pub(crate) const DEFAULT_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Notification that a replica was taken out of or put back into rotation
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HealthEvent {
+    /// Replica address in `host:port/database` form
+    pub endpoint: String,
+    /// Whether the replica is now serving reads
+    pub healthy: bool,
+}
+
+/// Spawn a task that checks the health and replication lag of every replica on an interval
+///
+/// The first check runs after `delay`. The task holds only a weak reference
+/// and stops once the replicas are dropped, or when aborted through the returned handle.
+pub(crate) fn spawn(replicas: &Arc<Replicas>, interval: Duration, delay: Duration, timeout: Duration) -> AbortHandle {
+    let replicas = Arc::downgrade(replicas);
+
+    let task = tokio::spawn(async move {
+        let mut ticker = tokio::time::interval_at(Instant::now() + delay, interval);
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+        loop {
+            ticker.tick().await;
+
+            let Some(replicas) = replicas.upgrade() else {
+                break;
+            };
+
+            for replica in replicas.iter() {
+                let probe = probe(&replica.pool, &replica.counters, timeout).await;
+
+                if let Some(probe) = &probe {
+                    replica.set_lag(probe.lag);
+                    replica.set_position(probe.position);
+                }
+
+                replicas.set_healthy(replica, probe.is_some());
+            }
+        }
+    });
+
+    task.abort_handle()
+}
+
+/// Replication lag in seconds, zero for a primary or a replica that replayed everything it
+/// received, followed by the WAL position the server has replayed or written up to
+const PROBE_QUERY: &str = "
+    SELECT
+        CASE
+            WHEN NOT pg_is_in_recovery() THEN 0
+            WHEN pg_last_wal_receive_lsn() = pg_last_wal_replay_lsn() THEN 0
+            ELSE COALESCE(EXTRACT(EPOCH FROM now() - pg_last_xact_replay_timestamp()), 0)
+        END::float8,
+        CASE
+            WHEN pg_is_in_recovery() THEN pg_last_wal_replay_lsn()
+            ELSE pg_current_wal_lsn()
+        END::text
+";
+
+/// Outcome of a successful replica health check
+pub(crate) struct Probe {
+    /// How far the replica is behind its primary
+    pub lag: Duration,
+    /// WAL position the replica has replayed up to, if known
+    pub position: Option<ConsistencyToken>,
+}
+
+/// Measure how far a replica is behind its primary
+///
+/// The probe opens a dedicated connection with the pool's current connect options,
+/// so a replica whose pool is busy serving reads is not mistaken for a dead one.
+///
+/// # Returns
+/// `None` if the replica did not answer within `timeout`
+pub(crate) async fn probe(pool: &Pool<Postgres>, counters: &PoolCounters, timeout: Duration) -> Option<Probe> {
+    let query = dedicated(pool, async |connection| {
+        sqlx::query_as::<_, (f64, Option<String>)>(PROBE_QUERY).fetch_one(connection).await
+    });
+    let (seconds, position) = spans::instrument(Operation::HealthCheck, counters, within(counters, timeout, query))
+        .await
+        .ok()?;
+
+    Some(Probe {
+        lag: Duration::try_from_secs_f64(seconds).unwrap_or_default(),
+        position: position.and_then(|position| position.parse().ok()),
+    })
+}
+
+/// Run `query` over a new connection to the server of `pool`, bypassing the pool itself
+async fn dedicated<T>(
+    pool: &Pool<Postgres>,
+    query: impl AsyncFnOnce(&mut PgConnection) -> Result<T, sqlx::Error>,
+) -> Result<T, sqlx::Error> {
+    let mut connection = pool.connect_options().connect().await?;
+    let result = query(&mut connection).await;
+    let _ = connection.close().await;
+
+    result
+}
+
+/// Run a query, failing with a description of the problem if it errors or takes longer than `timeout`
+///
+/// Errors are also recorded on `counters`, so rejected credentials get refreshed.
+async fn within<T>(
+    counters: &PoolCounters,
+    timeout: Duration,
+    query: impl Future<Output = Result<T, sqlx::Error>>,
+) -> Result<T, String> {
+    match tokio::time::timeout(timeout, query).await {
+        Ok(result) => result.map_err(|error| {
+            counters.failed(&error);
+            error.to_string()
+        }),
+        Err(_) => Err(format!("no response within {timeout:?}")),
+    }
+}
+
+/// Health of every node, as returned by [`health`](crate::health)
+///
+/// Suitable for backing readiness and liveness probes.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct HealthReport {
+    /// Health of the writer
+    pub writer: NodeHealth,
+    /// Health of each replica, empty when reads use the writer
+    pub readers: Vec<NodeHealth>,
+}
+
+impl HealthReport {
+    /// Whether the service can handle traffic, i.e. the writer is reachable
+    ///
+    /// Reads fall back to the writer, so unreachable replicas do not affect readiness.
+    pub fn is_ready(&self) -> bool {
+        self.writer.reachable
+    }
+
+    /// Whether every node is reachable
+    pub fn is_healthy(&self) -> bool {
+        self.writer.reachable && self.readers.iter().all(|reader| reader.reachable)
+    }
+}
+
+/// Health of a single database node
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct NodeHealth {
+    /// Role the node plays
+    pub role: Role,
+    /// Node address in `host:port/database` form
+    pub endpoint: String,
+    /// Whether the node accepted a new connection and answered within the health check timeout
+    pub reachable: bool,
+    /// Whether the node currently serves its role (replicas may be out of rotation)
+    pub in_rotation: bool,
+    /// Round-trip time of a `SELECT 1`
+    pub latency: Option<Duration>,
+    /// Number of connections in the pool
+    pub size: u32,
+    /// Number of idle connections in the pool
+    pub idle: usize,
+    /// Postgres server version, e.g. `16.2`
+    pub server_version: Option<String>,
+    /// Whether the server is a standby replaying WAL
+    pub in_recovery: Option<bool>,
+    /// Replication lag measured by the last background health check
+    pub replication_lag: Option<Duration>,
+    /// Why the node is not reachable
+    pub error: Option<String>,
+}
+
+impl NodeHealth {
+    /// Check a node by running a few trivial queries over a dedicated connection
+    ///
+    /// The pool is bypassed, so a node whose pool is exhausted is still reported
+    /// as reachable. `size` and `idle` show how busy the pool is.
+    pub(crate) async fn check(counters: &PoolCounters, pool: &Pool<Postgres>, timeout: Duration) -> Self {
+        let mut node = Self {
+            role: counters.role,
+            endpoint: counters.endpoint.clone(),
+            reachable: false,
+            in_rotation: true,
+            latency: None,
+            size: pool.size(),
+            idle: pool.num_idle(),
+            server_version: None,
+            in_recovery: None,
+            replication_lag: None,
+            error: None,
+        };
+
+        match spans::instrument(Operation::HealthCheck, counters, within(counters, timeout, dedicated(pool, inspect))).await {
+            Ok((latency, server_version, in_recovery)) => {
+                node.reachable = true;
+                node.latency = Some(latency);
+                node.server_version = Some(server_version);
+                node.in_recovery = Some(in_recovery);
+            }
+            Err(error) => node.error = Some(error),
+        }
+
+        node
+    }
+}
+
+/// Time a `SELECT 1` and fetch the server version and recovery state
+async fn inspect(connection: &mut PgConnection) -> Result<(Duration, String, bool), sqlx::Error> {
+    let started = Instant::now();
+    sqlx::query("SELECT 1").execute(&mut *connection).await?;
+    let latency = started.elapsed();
+
+    let (server_version, in_recovery) = sqlx::query_as::<_, (String, bool)>(
+        "SELECT current_setting('server_version'), pg_is_in_recovery()",
+    )
+    .fetch_one(connection)
+    .await?;
+
+    Ok((latency, server_version, in_recovery))
+}
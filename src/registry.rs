@@ -0,0 +1,150 @@
+//! Named database instances for services talking to several clusters
+
+use crate::{Database, DatabaseBuilder, DatabaseError, INSTALLING, retire};
+use arc_swap::ArcSwapOption;
+use sqlx::{Pool, Postgres};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+// Named instances, replaced as a whole whenever one is added or reloaded
+//
+// Instances are leaked like the global one, so pools handed out by
+// `reader_of()` and `writer_of()` stay valid after a reload.
+static NAMED: ArcSwapOption<HashMap<String, &'static Database>> = ArcSwapOption::const_empty();
+
+/// Initialize a named database instance
+///
+/// Named instances sit next to the global one and are configured from the
+/// same environment variables with the name in upper case prepended, e.g.
+/// `ANALYTICS_DATABASE_URL` and `ANALYTICS_DATABASE_READ_URLS` for `analytics`.
+/// Each has its own writer and readers. Calling this again for an initialized
+/// name does nothing.
+///
+/// # Example
+/// ```no_run
+/// async fn example() -> Result<(), sqlx::Error> {
+///     database::init().await;
+///     database::init_named("analytics").await;
+///
+///     sqlx::query("SELECT count(*) FROM events")
+///         .fetch_one(database::reader_of("analytics"))
+///         .await?;
+///
+///     Ok(())
+/// }
+/// ```
+///
+/// # Panics
+/// If required environment variables are missing or the writer fails to connect.
+/// Use [`try_init_named`] to handle these failures instead.
+pub async fn init_named(name: &str) {
+    if let Err(error) = try_init_named(name).await {
+        panic!("{name}: {error}");
+    }
+}
+
+/// Initialize a named database instance without panicking
+///
+/// Behaves like [`init_named`], but returns the failure so the caller can log,
+/// retry, or fall back.
+///
+/// # Errors
+/// A [`DatabaseError`] describing which pool failed and why
+pub async fn try_init_named(name: &str) -> Result<(), DatabaseError> {
+    let _installing = INSTALLING.lock().await;
+
+    if named(name).is_none() {
+        install(name, DatabaseBuilder::from_env_named(name)?.build().await?);
+    }
+
+    Ok(())
+}
+
+/// Rebuild a named database instance from fresh configuration and swap it in
+///
+/// Works like [`reload`](crate::reload) for the global instance, and
+/// initializes the named instance if it has not been initialized yet.
+///
+/// # Errors
+/// A [`DatabaseError`] describing which pool failed and why. The current
+/// instance stays in place when reloading fails.
+pub async fn reload_named(name: &str) -> Result<(), DatabaseError> {
+    let _installing = INSTALLING.lock().await;
+    let database = DatabaseBuilder::from_env_named(name)?.build().await?;
+    let grace_period = database.reload_grace_period;
+
+    if let Some(replaced) = install(name, database) {
+        retire(replaced, grace_period);
+    }
+
+    Ok(())
+}
+
+/// Get a named database instance
+///
+/// # Returns
+/// `None` if no instance with that name has been initialized
+pub fn named(name: &str) -> Option<&'static Database> {
+    NAMED.load().as_deref().and_then(|instances| instances.get(name).copied())
+}
+
+/// Get a reference to the reader connection pool of a named instance
+///
+/// # Panics
+/// If the instance has not been initialized via `init_named()`
+pub fn reader_of<'a>(name: &str) -> &'a Pool<Postgres> {
+    if let Some(database) = named(name) {
+        return database.reader();
+    }
+
+    panic!("Database {name:?} not initialized")
+}
+
+/// Get a reference to the writer connection pool of a named instance
+///
+/// # Panics
+/// If the instance has not been initialized via `init_named()`
+pub fn writer_of<'a>(name: &str) -> &'a Pool<Postgres> {
+    if let Some(database) = named(name) {
+        return database.writer();
+    }
+
+    panic!("Database {name:?} not initialized")
+}
+
+/// Get a reference to the reader connection pool of a named instance without panicking
+///
+/// # Returns
+/// `None` if the instance has not been initialized yet
+pub fn try_reader_of<'a>(name: &str) -> Option<&'a Pool<Postgres>> {
+    named(name).map(|database| database.reader())
+}
+
+/// Get a reference to the writer connection pool of a named instance without panicking
+///
+/// # Returns
+/// `None` if the instance has not been initialized yet
+pub fn try_writer_of<'a>(name: &str) -> Option<&'a Pool<Postgres>> {
+    named(name).map(|database| database.writer())
+}
+
+/// Every named instance currently installed
+pub(crate) fn instances() -> Vec<&'static Database> {
+    NAMED.load().as_deref().map(|instances| instances.values().copied().collect()).unwrap_or_default()
+}
+
+/// Make `database` the instance with the given name
+///
+/// Callers hold `INSTALLING`, so concurrent installs cannot lose each other's updates.
+///
+/// # Returns
+/// The instance that was replaced, if any
+fn install(name: &str, database: Database) -> Option<&'static Database> {
+    let database: &'static Database = Box::leak(Box::new(database));
+    let mut instances = NAMED.load().as_deref().cloned().unwrap_or_default();
+    let replaced = instances.insert(name.to_string(), database);
+
+    NAMED.store(Some(Arc::new(instances)));
+
+    replaced
+}
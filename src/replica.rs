@@ -0,0 +1,417 @@
+//! Read replica set and reader selection strategies
+
+use crate::consistency::{self, POLL_INTERVAL};
+use crate::redact::redact;
+use crate::stats::PoolCounters;
+use crate::{ConsistencyToken, HealthEvent};
+use sqlx::{Pool, Postgres};
+use sqlx::postgres::PgConnectOptions;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::{fmt, time::Duration};
+use tokio::sync::broadcast;
+use tokio::task::JoinSet;
+use tokio::time::{Instant, sleep};
+
+/// Strategy used by [`Database::reader`](crate::Database::reader) to pick a replica
+///
+/// Only replicas that passed their last health check and are within the
+/// configured replication lag are considered.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum ReadStrategy {
+    /// Cycle through the replicas in order
+    #[default]
+    RoundRobin,
+    /// Pick a replica at random
+    Random,
+    /// Pick the replica with the fewest connections in use
+    LeastBusy,
+}
+
+impl ReadStrategy {
+    /// Name of the strategy as accepted by `DATABASE_READ_STRATEGY`
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::RoundRobin => "round-robin",
+            Self::Random => "random",
+            Self::LeastBusy => "least-busy",
+        }
+    }
+}
+
+impl fmt::Display for ReadStrategy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for ReadStrategy {
+    type Err = String;
+
+    fn from_str(string: &str) -> Result<Self, Self::Err> {
+        match string.trim().to_ascii_lowercase().replace('_', "-").as_str() {
+            "round-robin" => Ok(Self::RoundRobin),
+            "random" => Ok(Self::Random),
+            "least-busy" => Ok(Self::LeastBusy),
+            _ => Err(format!("unknown read strategy: {string}")),
+        }
+    }
+}
+
+/// A single read replica
+pub(crate) struct Replica {
+    /// Connection string used to establish the connection
+    pub url: String,
+    /// Replica address in `host:port/database` form, free of credentials
+    pub endpoint: String,
+    /// Connection pool for the replica
+    pub pool: Pool<Postgres>,
+    /// Statistics counters of the pool
+    pub counters: Arc<PoolCounters>,
+    /// Whether the replica passed its last health check
+    healthy: AtomicBool,
+    /// Replication lag in milliseconds measured by the last health check
+    lag: AtomicU64,
+    /// Last known WAL position the replica has replayed up to, zero if unknown
+    position: AtomicU64,
+}
+
+// Marks replication lag that has not been measured yet
+const UNKNOWN_LAG: u64 = u64::MAX;
+
+impl Replica {
+    /// Create a replica, in rotation if `healthy`
+    pub fn new(
+        url: String,
+        options: &PgConnectOptions,
+        pool: Pool<Postgres>,
+        counters: Arc<PoolCounters>,
+        healthy: bool,
+    ) -> Self {
+        Self {
+            url,
+            endpoint: endpoint(options),
+            pool,
+            counters,
+            healthy: AtomicBool::new(healthy),
+            lag: AtomicU64::new(UNKNOWN_LAG),
+            position: AtomicU64::new(0),
+        }
+    }
+
+    /// Whether the replica is currently in rotation
+    pub fn is_healthy(&self) -> bool {
+        self.healthy.load(Ordering::Relaxed)
+    }
+
+    /// Replication lag measured by the last successful health check
+    ///
+    /// # Returns
+    /// `None` if the lag has not been measured yet
+    pub fn lag(&self) -> Option<Duration> {
+        match self.lag.load(Ordering::Relaxed) {
+            UNKNOWN_LAG => None,
+            millis => Some(Duration::from_millis(millis)),
+        }
+    }
+
+    /// Record a lag measurement
+    pub fn set_lag(&self, lag: Duration) {
+        let millis = u64::try_from(lag.as_millis()).unwrap_or(UNKNOWN_LAG - 1);
+        self.lag.store(millis.min(UNKNOWN_LAG - 1), Ordering::Relaxed);
+    }
+
+    /// Whether the replica is known to have replayed up to `token`
+    pub fn has_replayed(&self, token: ConsistencyToken) -> bool {
+        self.position.load(Ordering::Relaxed) >= token.0
+    }
+
+    /// Record the WAL position the replica has replayed up to
+    pub fn set_position(&self, position: Option<ConsistencyToken>) {
+        if let Some(position) = position {
+            self.position.fetch_max(position.0, Ordering::Relaxed);
+        }
+    }
+
+    /// Whether the replica is healthy and no further behind than `max_lag`
+    ///
+    /// Replicas whose lag has not been measured yet are assumed to be fresh.
+    pub fn is_available(&self, max_lag: Option<Duration>) -> bool {
+        self.is_healthy() && match (max_lag, self.lag()) {
+            (Some(max_lag), Some(lag)) => lag <= max_lag,
+            _ => true,
+        }
+    }
+}
+
+/// The set of read replicas a [`Database`](crate::Database) balances reads across
+pub(crate) struct Replicas {
+    replicas: Vec<Replica>,
+    strategy: ReadStrategy,
+    // Replicas further behind than this are skipped by `select()`
+    max_lag: Option<Duration>,
+    // Position of the next replica for round-robin selection
+    next: AtomicUsize,
+    // Publishes replicas leaving or rejoining the rotation
+    events: broadcast::Sender<HealthEvent>,
+}
+
+impl Replicas {
+    pub fn new(replicas: Vec<Replica>, strategy: ReadStrategy, max_lag: Option<Duration>) -> Self {
+        let (events, _) = broadcast::channel(16);
+
+        Self { replicas, strategy, max_lag, next: AtomicUsize::new(0), events }
+    }
+
+    /// Iterate over every replica in the set
+    pub fn iter(&self) -> impl Iterator<Item = &Replica> {
+        self.replicas.iter()
+    }
+
+    /// Iterate over the replicas currently eligible to serve reads
+    pub fn available(&self) -> impl Iterator<Item = &Replica> {
+        self.iter().filter(|replica| self.is_in_rotation(replica))
+    }
+
+    /// Whether a replica is healthy and within the configured lag
+    pub fn is_in_rotation(&self, replica: &Replica) -> bool {
+        replica.is_available(self.max_lag)
+    }
+
+    /// Subscribe to replica health changes
+    pub fn subscribe(&self) -> broadcast::Receiver<HealthEvent> {
+        self.events.subscribe()
+    }
+
+    /// Record the outcome of a health check, notifying subscribers on change
+    pub fn set_healthy(&self, replica: &Replica, healthy: bool) {
+        if replica.healthy.swap(healthy, Ordering::Relaxed) != healthy {
+            // Sending only fails when nobody is subscribed
+            let _ = self.events.send(HealthEvent { endpoint: replica.endpoint.clone(), healthy });
+        }
+    }
+
+    /// Pick a healthy replica within the configured lag according to the strategy
+    ///
+    /// # Returns
+    /// `None` if no replica is available
+    pub fn select(&self) -> Option<&Replica> {
+        self.select_by(|replica| replica.is_available(self.max_lag))
+    }
+
+    /// Pick a healthy replica no further behind than `max_lag`
+    ///
+    /// The stricter of `max_lag` and the configured threshold applies. Unlike
+    /// [`select`](Self::select), replicas whose lag has not been measured yet
+    /// are skipped, since the caller asked for a freshness guarantee.
+    pub fn select_within(&self, max_lag: Duration) -> Option<&Replica> {
+        let max_lag = self.max_lag.map_or(max_lag, |configured| configured.min(max_lag));
+
+        self.select_by(|replica| replica.is_healthy() && replica.lag().is_some_and(|lag| lag <= max_lag))
+    }
+
+    /// Pick a healthy replica that has replayed up to `token`
+    ///
+    /// Uses the positions recorded by the health checker when they suffice,
+    /// and otherwise polls the healthy replicas concurrently until one catches
+    /// up, so a slow replica cannot hold up the others.
+    ///
+    /// # Returns
+    /// `None` if no replica caught up within `timeout`
+    pub async fn select_after(&self, token: ConsistencyToken, timeout: Duration) -> Option<&Replica> {
+        let deadline = Instant::now() + timeout;
+        let mut polled = false;
+
+        loop {
+            let replica = self.select_by(|replica| replica.is_healthy() && replica.has_replayed(token));
+            if replica.is_some() || Instant::now() >= deadline || !self.iter().any(Replica::is_healthy) {
+                return replica;
+            }
+
+            if polled {
+                sleep(POLL_INTERVAL.min(deadline.saturating_duration_since(Instant::now()))).await;
+            }
+
+            let timeout = deadline.saturating_duration_since(Instant::now());
+            let mut polls = JoinSet::new();
+
+            for (index, replica) in self.replicas.iter().enumerate().filter(|(_, replica)| replica.is_healthy()) {
+                let pool = replica.pool.clone();
+                polls.spawn(async move { (index, consistency::position(&pool, timeout).await) });
+            }
+
+            // Stop at the first replica that caught up; dropping the set cancels the other polls
+            while let Some(Ok((index, position))) = polls.join_next().await {
+                self.replicas[index].set_position(position);
+
+                if position.is_some_and(|position| position >= token) {
+                    break;
+                }
+            }
+
+            polled = true;
+        }
+    }
+
+    /// Pick a replica matching `eligible` according to the configured strategy
+    ///
+    /// # Returns
+    /// `None` if no replica matches
+    pub fn select_by(&self, eligible: impl Fn(&Replica) -> bool) -> Option<&Replica> {
+        let len = self.replicas.len();
+        if len == 0 {
+            return None;
+        }
+
+        // Scan from the strategy's starting point so skipped replicas do not skew selection
+        let start = match self.strategy {
+            ReadStrategy::RoundRobin | ReadStrategy::LeastBusy => self.next.fetch_add(1, Ordering::Relaxed),
+            ReadStrategy::Random => random() as usize,
+        };
+
+        let mut candidates = (0..len)
+            .map(|offset| &self.replicas[start.wrapping_add(offset) % len])
+            .filter(|replica| eligible(replica));
+
+        match self.strategy {
+            ReadStrategy::RoundRobin | ReadStrategy::Random => candidates.next(),
+            ReadStrategy::LeastBusy => candidates.min_by_key(|replica| in_use(&replica.pool)),
+        }
+    }
+}
+
+impl fmt::Debug for Replica {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Replica")
+            .field("url", &redact(&self.url))
+            .field("pool", &self.pool)
+            .field("healthy", &self.is_healthy())
+            .field("lag", &self.lag())
+            .finish()
+    }
+}
+
+impl fmt::Debug for Replicas {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Replicas")
+            .field("replicas", &self.replicas)
+            .field("strategy", &self.strategy)
+            .field("max_lag", &self.max_lag)
+            .finish()
+    }
+}
+
+/// Describe where a connection points to without exposing credentials
+pub(crate) fn endpoint(options: &PgConnectOptions) -> String {
+    match options.get_database() {
+        Some(database) => format!("{}:{}/{database}", options.get_host(), options.get_port()),
+        None => format!("{}:{}", options.get_host(), options.get_port()),
+    }
+}
+
+/// Number of connections currently checked out of a pool
+fn in_use(pool: &Pool<Postgres>) -> u32 {
+    pool.size().saturating_sub(pool.num_idle() as u32)
+}
+
+/// Cheap pseudo-random number, good enough for spreading load
+pub(crate) fn random() -> u64 {
+    RandomState::new().build_hasher().finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Role;
+    use sqlx::postgres::PgPoolOptions;
+
+    /// Replica with a lazy pool that never connects, in rotation if `healthy`
+    fn replica(host: &str, healthy: bool) -> Replica {
+        let options = PgConnectOptions::new_without_pgpass().host(host).port(5432).database("app");
+        let pool = PgPoolOptions::new().connect_lazy_with(options.clone());
+        let counters = Arc::new(PoolCounters::new(Role::Reader, &options, None));
+
+        Replica::new(format!("postgres://{host}:5432/app"), &options, pool, counters, healthy)
+    }
+
+    /// Hosts of the replicas picked by `count` consecutive selections
+    fn picks(replicas: &Replicas, count: usize) -> Vec<String> {
+        (0..count)
+            .map(|_| replicas.select().map_or("none", |replica| &replica.endpoint[..1]).to_string())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn round_robin_cycles_through_replicas() {
+        let replicas = Replicas::new(
+            vec![replica("a", true), replica("b", true), replica("c", true)],
+            ReadStrategy::RoundRobin,
+            None,
+        );
+
+        assert_eq!(picks(&replicas, 4), ["a", "b", "c", "a"]);
+    }
+
+    #[tokio::test]
+    async fn ineligible_replicas_are_skipped() {
+        let replicas = Replicas::new(
+            vec![replica("a", true), replica("b", false), replica("c", true)],
+            ReadStrategy::RoundRobin,
+            None,
+        );
+
+        // Starting at the unhealthy replica moves on to the next one rather than back to the first
+        assert_eq!(picks(&replicas, 3), ["a", "c", "c"]);
+
+        let replicas = Replicas::new(
+            vec![replica("a", true), replica("b", true)],
+            ReadStrategy::RoundRobin,
+            Some(Duration::from_secs(1)),
+        );
+        replicas.replicas[0].set_lag(Duration::from_secs(5));
+
+        assert_eq!(picks(&replicas, 2), ["b", "b"]);
+        // Unlike `select`, `select_within` does not trust replicas whose lag was never measured
+        assert!(replicas.select_within(Duration::from_secs(1)).is_none());
+    }
+
+    #[tokio::test]
+    async fn no_replica_is_picked_when_none_is_eligible() {
+        let replicas = Replicas::new(
+            vec![replica("a", false), replica("b", false)],
+            ReadStrategy::Random,
+            None,
+        );
+
+        assert_eq!(picks(&replicas, 2), ["none", "none"]);
+        assert!(Replicas::new(Vec::new(), ReadStrategy::LeastBusy, None).select().is_none());
+    }
+
+    #[tokio::test]
+    async fn least_busy_rotates_between_equally_busy_replicas() {
+        let replicas = Replicas::new(
+            vec![replica("a", true), replica("b", true), replica("c", false)],
+            ReadStrategy::LeastBusy,
+            None,
+        );
+
+        // All pools are idle, so ties go to the next replica in turn instead of always the first
+        assert_eq!(picks(&replicas, 4), ["a", "b", "a", "a"]);
+    }
+
+    #[test]
+    fn read_strategies_parse_case_insensitively() {
+        assert_eq!("round-robin".parse(), Ok(ReadStrategy::RoundRobin));
+        assert_eq!("round_robin".parse(), Ok(ReadStrategy::RoundRobin));
+        assert_eq!(" Least_Busy ".parse(), Ok(ReadStrategy::LeastBusy));
+        assert_eq!("RANDOM".parse(), Ok(ReadStrategy::Random));
+        assert!("fastest".parse::<ReadStrategy>().is_err());
+
+        for strategy in [ReadStrategy::RoundRobin, ReadStrategy::Random, ReadStrategy::LeastBusy] {
+            assert_eq!(strategy.to_string().parse(), Ok(strategy));
+        }
+    }
+}
@@ -0,0 +1,287 @@
+//! Retrying connection attempts with exponential backoff
+
+use crate::config::Env;
+use crate::replica::random;
+use crate::{DatabaseError, Role};
+use std::future::Future;
+use std::time::Duration;
+use tokio::time::{Instant, sleep, timeout};
+
+/// How connecting a pool is retried when the server is not ready yet
+///
+/// Delays double after every failed attempt, starting at `initial_delay` and
+/// capped at `max_delay`. Authentication and configuration errors are never
+/// retried.
+///
+/// # Example
+/// ```
+/// use database::RetryPolicy;
+/// use std::time::Duration;
+///
+/// let policy = RetryPolicy {
+///     max_attempts: 10,
+///     deadline: Some(Duration::from_secs(60)),
+///     ..RetryPolicy::default()
+/// };
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Total number of connection attempts, including the first (1 disables retrying)
+    pub max_attempts: u32,
+    /// Delay before the second attempt
+    pub initial_delay: Duration,
+    /// Upper bound for the delay between attempts
+    pub max_delay: Duration,
+    /// Randomize each delay between half and the full value to avoid thundering herds
+    pub jitter: bool,
+    /// Give up once this much time has passed since the first attempt
+    pub deadline: Option<Duration>,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 1,
+            initial_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(30),
+            jitter: true,
+            deadline: None,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Read the retry policy for a role from environment variables
+    ///
+    /// Like the pool settings, each variable may be given per role, e.g.
+    /// `DATABASE_READ_RETRY_MAX_ATTEMPTS`, falling back to the shared name.
+    ///
+    /// # Environment Variables
+    /// - RETRY_MAX_ATTEMPTS: Total number of connection attempts
+    /// - RETRY_INITIAL_DELAY_MS: Delay before the second attempt in milliseconds
+    /// - RETRY_MAX_DELAY_MS: Upper bound for the delay in milliseconds
+    /// - RETRY_JITTER: `true`/`false`, `1`/`0` or `yes`/`no`
+    /// - RETRY_DEADLINE_MS: Time after which to give up in milliseconds
+    ///
+    /// # Errors
+    /// [`DatabaseError::InvalidConfiguration`] if a variable cannot be parsed
+    pub fn from_env(role: Role) -> Result<Self, DatabaseError> {
+        Self::read(&Env::default(), role)
+    }
+
+    /// Read the retry policy for a role from variables under the prefix of `env`
+    pub(crate) fn read(env: &Env, role: Role) -> Result<Self, DatabaseError> {
+        let default = Self::default();
+
+        Ok(Self {
+            max_attempts: env.parse_var(role, "RETRY_MAX_ATTEMPTS")?.unwrap_or(default.max_attempts),
+            initial_delay: env.parse_var(role, "RETRY_INITIAL_DELAY_MS")?
+                .map_or(default.initial_delay, Duration::from_millis),
+            max_delay: env.parse_var(role, "RETRY_MAX_DELAY_MS")?.map_or(default.max_delay, Duration::from_millis),
+            jitter: env.parse_role_flag(role, "RETRY_JITTER")?.unwrap_or(default.jitter),
+            deadline: env.parse_var(role, "RETRY_DEADLINE_MS")?.map(Duration::from_millis),
+        })
+    }
+
+    /// Delay to wait after the given failed attempt (starting at 1)
+    fn delay(&self, attempt: u32) -> Duration {
+        let factor = 2u32.saturating_pow(attempt.saturating_sub(1));
+        let delay = self.initial_delay.saturating_mul(factor).min(self.max_delay);
+
+        if !self.jitter {
+            return delay;
+        }
+
+        let half = delay / 2;
+        let spread = u64::try_from(half.as_millis()).unwrap_or(u64::MAX).saturating_add(1);
+
+        half + Duration::from_millis(random() % spread)
+    }
+
+    /// Run `attempt` until it succeeds, fails permanently, or the policy is exhausted
+    pub(crate) async fn run<T, F, Fut>(&self, role: Role, mut attempt: F) -> Result<T, DatabaseError>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T, DatabaseError>>,
+    {
+        let deadline = self.deadline.map(|deadline| Instant::now() + deadline);
+        let mut attempts = 0;
+
+        loop {
+            attempts += 1;
+
+            let result = match deadline {
+                Some(deadline) => timeout(deadline.saturating_duration_since(Instant::now()), attempt())
+                    .await
+                    .unwrap_or(Err(DatabaseError::Timeout { role })),
+                None => attempt().await,
+            };
+
+            let error = match result {
+                Ok(value) => return Ok(value),
+                Err(error) => error,
+            };
+
+            let delay = self.delay(attempts);
+            let exhausted = !is_retryable(&error)
+                || attempts >= self.max_attempts
+                || deadline.is_some_and(|deadline| Instant::now() + delay >= deadline);
+
+            if exhausted {
+                if attempts > 1 {
+                    log::error!("Database connection attempt {attempts} failed: {error}; giving up");
+                }
+
+                return Err(error);
+            }
+
+            log::warn!(
+                "Database connection attempt {attempts} of {} failed: {error}; retrying in {delay:?}",
+                self.max_attempts,
+            );
+
+            sleep(delay).await;
+        }
+    }
+}
+
+/// Whether waiting could make a failed connection attempt succeed
+fn is_retryable(error: &DatabaseError) -> bool {
+    matches!(
+        error,
+        DatabaseError::Unreachable { .. } | DatabaseError::Timeout { .. } | DatabaseError::Connection { .. }
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io;
+
+    fn policy(max_attempts: u32) -> RetryPolicy {
+        RetryPolicy {
+            max_attempts,
+            initial_delay: Duration::from_millis(100),
+            max_delay: Duration::from_secs(1),
+            jitter: false,
+            deadline: None,
+        }
+    }
+
+    fn unreachable() -> DatabaseError {
+        DatabaseError::Unreachable {
+            role: Role::Writer,
+            source: sqlx::Error::Io(io::ErrorKind::ConnectionRefused.into()),
+        }
+    }
+
+    /// Run `policy` with attempts that fail with `error`, returning the error and the number of attempts
+    async fn failing(policy: &RetryPolicy, error: fn() -> DatabaseError) -> (DatabaseError, u32) {
+        let mut attempts = 0;
+        let result = policy
+            .run(Role::Writer, || {
+                attempts += 1;
+                async move { Err::<(), _>(error()) }
+            })
+            .await;
+
+        (result.unwrap_err(), attempts)
+    }
+
+    #[test]
+    fn delay_doubles_up_to_max_delay() {
+        let policy = policy(10);
+        let delays = (1..=6).map(|attempt| policy.delay(attempt).as_millis()).collect::<Vec<_>>();
+
+        assert_eq!(delays, [100, 200, 400, 800, 1000, 1000]);
+        assert_eq!(policy.delay(u32::MAX), Duration::from_secs(1));
+    }
+
+    #[test]
+    fn jitter_stays_between_half_and_full_delay() {
+        let policy = RetryPolicy { jitter: true, ..policy(10) };
+
+        for attempt in 1..=6 {
+            let full = RetryPolicy { jitter: false, ..policy.clone() }.delay(attempt);
+
+            for _ in 0..100 {
+                let delay = policy.delay(attempt);
+                assert!(delay >= full / 2 && delay <= full, "{delay:?} outside {:?}..={full:?}", full / 2);
+            }
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn permanent_errors_are_not_retried() {
+        let authentication = || DatabaseError::Authentication {
+            role: Role::Writer,
+            source: sqlx::Error::Protocol("password authentication failed".to_string()),
+        };
+        let invalid_url = || DatabaseError::InvalidUrl {
+            role: Role::Writer,
+            source: sqlx::Error::Configuration("invalid port".into()),
+        };
+
+        let (error, attempts) = failing(&policy(5), authentication).await;
+        assert!(matches!(error, DatabaseError::Authentication { .. }));
+        assert_eq!(attempts, 1);
+
+        let (error, attempts) = failing(&policy(5), invalid_url).await;
+        assert!(matches!(error, DatabaseError::InvalidUrl { .. }));
+        assert_eq!(attempts, 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn retries_up_to_max_attempts() {
+        let started = Instant::now();
+        let (error, attempts) = failing(&policy(4), unreachable).await;
+
+        assert!(matches!(error, DatabaseError::Unreachable { .. }));
+        assert_eq!(attempts, 4);
+        assert_eq!(started.elapsed(), Duration::from_millis(100 + 200 + 400));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn stops_retrying_before_the_deadline() {
+        let policy = RetryPolicy { deadline: Some(Duration::from_millis(500)), ..policy(10) };
+        let started = Instant::now();
+        let (_, attempts) = failing(&policy, unreachable).await;
+
+        // Attempts at 0, 100 and 300ms; waiting another 400ms would pass the deadline
+        assert_eq!(attempts, 3);
+        assert!(started.elapsed() < Duration::from_millis(500));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn times_out_attempts_at_the_deadline() {
+        let policy = RetryPolicy { deadline: Some(Duration::from_secs(2)), ..policy(10) };
+        let started = Instant::now();
+        let result = policy.run(Role::Reader, std::future::pending::<Result<(), DatabaseError>>).await;
+
+        assert!(matches!(result, Err(DatabaseError::Timeout { role: Role::Reader })));
+        assert_eq!(started.elapsed(), Duration::from_secs(2));
+    }
+
+    #[test]
+    fn reads_jitter_as_a_role_flag() {
+        let env = Env::named("retry_policy_test");
+
+        // SAFETY: the variables are unique to this test and no other thread reads them
+        unsafe {
+            std::env::set_var("RETRY_POLICY_TEST_DATABASE_RETRY_JITTER", "no");
+            std::env::set_var("RETRY_POLICY_TEST_DATABASE_READ_RETRY_JITTER", "1");
+        }
+
+        assert!(!RetryPolicy::read(&env, Role::Writer).unwrap().jitter);
+        assert!(RetryPolicy::read(&env, Role::Reader).unwrap().jitter);
+
+        unsafe { std::env::set_var("RETRY_POLICY_TEST_DATABASE_READ_RETRY_JITTER", "sometimes") };
+
+        let error = RetryPolicy::read(&env, Role::Reader).unwrap_err();
+        assert!(matches!(
+            error,
+            DatabaseError::InvalidConfiguration { role: Role::Reader, ref variable, .. }
+                if variable == "RETRY_POLICY_TEST_DATABASE_READ_RETRY_JITTER"
+        ));
+    }
+}
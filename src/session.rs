@@ -0,0 +1,170 @@
+//! Session settings applied to every new connection
+
+use crate::config::Env;
+use crate::{DatabaseError, Role};
+use sqlx::PgConnection;
+use std::time::Duration;
+
+/// Server settings applied to every connection of a pool when it opens
+///
+/// Saves running `SET` before each query. Any field left as `None` keeps the
+/// server default. Settings are applied with `set_config`, so values are
+/// passed as query parameters and never spliced into SQL.
+///
+/// # Example
+/// ```
+/// use database::{PoolConfig, SessionSettings};
+/// use std::time::Duration;
+///
+/// let config = PoolConfig {
+///     session: SessionSettings {
+///         statement_timeout: Some(Duration::from_secs(30)),
+///         time_zone: Some("UTC".to_string()),
+///         search_path: Some("app, public".to_string()),
+///         ..SessionSettings::default()
+///     },
+///     ..PoolConfig::default()
+/// };
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SessionSettings {
+    /// Abort statements running longer than this
+    pub statement_timeout: Option<Duration>,
+    /// Abort statements waiting longer than this for a lock
+    pub lock_timeout: Option<Duration>,
+    /// End sessions that stay idle inside an open transaction for longer than this
+    pub idle_in_transaction_session_timeout: Option<Duration>,
+    /// Time zone for displaying and interpreting timestamps, e.g. `UTC`
+    pub time_zone: Option<String>,
+    /// Schemas searched for unqualified names, e.g. `app, public`
+    pub search_path: Option<String>,
+}
+
+impl SessionSettings {
+    /// Read the session settings for a role from environment variables
+    ///
+    /// Like the pool settings, each variable may be given per role, e.g.
+    /// `DATABASE_WRITE_STATEMENT_TIMEOUT_MS`, falling back to the shared name.
+    ///
+    /// # Environment Variables
+    /// - STATEMENT_TIMEOUT_MS: Statement timeout in milliseconds
+    /// - LOCK_TIMEOUT_MS: Lock wait timeout in milliseconds
+    /// - IDLE_IN_TRANSACTION_SESSION_TIMEOUT_MS: Idle-in-transaction timeout in milliseconds
+    /// - TIME_ZONE: Session time zone
+    /// - SEARCH_PATH: Comma-separated schema search path
+    ///
+    /// # Errors
+    /// [`DatabaseError::InvalidConfiguration`] if a timeout cannot be parsed
+    pub fn from_env(role: Role) -> Result<Self, DatabaseError> {
+        Self::read(&Env::default(), role)
+    }
+
+    /// Read the session settings for a role from variables under the prefix of `env`
+    pub(crate) fn read(env: &Env, role: Role) -> Result<Self, DatabaseError> {
+        Ok(Self {
+            statement_timeout: env.parse_var(role, "STATEMENT_TIMEOUT_MS")?.map(Duration::from_millis),
+            lock_timeout: env.parse_var(role, "LOCK_TIMEOUT_MS")?.map(Duration::from_millis),
+            idle_in_transaction_session_timeout: env
+                .parse_var(role, "IDLE_IN_TRANSACTION_SESSION_TIMEOUT_MS")?
+                .map(Duration::from_millis),
+            time_zone: env.role_var(role, "TIME_ZONE").map(|(_, value)| value),
+            search_path: env.role_var(role, "SEARCH_PATH").map(|(_, value)| value),
+        })
+    }
+
+    /// Apply the settings to a freshly opened connection
+    pub(crate) async fn apply(&self, connection: &mut PgConnection) -> Result<(), sqlx::Error> {
+        let settings = self.settings();
+
+        if settings.is_empty() {
+            return Ok(());
+        }
+
+        let sql = statement(settings.len());
+        let mut query = sqlx::query(&sql);
+
+        for (name, value) in settings {
+            query = query.bind(name).bind(value);
+        }
+
+        query.execute(connection).await.map(|_| ())
+    }
+
+    /// Configured settings as server parameter names and values
+    fn settings(&self) -> Vec<(&'static str, String)> {
+        let milliseconds = |timeout: &Duration| format!("{}ms", timeout.as_millis());
+
+        [
+            ("statement_timeout", self.statement_timeout.as_ref().map(milliseconds)),
+            ("lock_timeout", self.lock_timeout.as_ref().map(milliseconds)),
+            (
+                "idle_in_transaction_session_timeout",
+                self.idle_in_transaction_session_timeout.as_ref().map(milliseconds),
+            ),
+            ("TimeZone", self.time_zone.clone()),
+            ("search_path", self.search_path.clone()),
+        ]
+        .into_iter()
+        .filter_map(|(name, value)| value.map(|value| (name, value)))
+        .collect()
+    }
+}
+
+/// Statement applying `count` settings in one round trip
+///
+/// Reads `SELECT set_config($1, $2, false), set_config($3, $4, false), ...`,
+/// binding each setting's name and value in turn.
+fn statement(count: usize) -> String {
+    let calls = (0..count)
+        .map(|index| format!("set_config(${}, ${}, false)", 2 * index + 1, 2 * index + 2))
+        .collect::<Vec<_>>()
+        .join(", ");
+
+    format!("SELECT {calls}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_configured_settings_are_applied() {
+        assert!(SessionSettings::default().settings().is_empty());
+
+        let settings = SessionSettings { lock_timeout: Some(Duration::from_secs(2)), ..SessionSettings::default() };
+
+        assert_eq!(settings.settings(), [("lock_timeout", "2000ms".to_string())]);
+    }
+
+    #[test]
+    fn settings_use_server_parameter_names() {
+        let settings = SessionSettings {
+            statement_timeout: Some(Duration::from_secs(30)),
+            lock_timeout: Some(Duration::from_millis(1500)),
+            idle_in_transaction_session_timeout: Some(Duration::from_micros(2500)),
+            time_zone: Some("UTC".to_string()),
+            search_path: Some("app, public".to_string()),
+        };
+
+        assert_eq!(
+            settings.settings(),
+            [
+                ("statement_timeout", "30000ms".to_string()),
+                ("lock_timeout", "1500ms".to_string()),
+                // The server takes whole milliseconds
+                ("idle_in_transaction_session_timeout", "2ms".to_string()),
+                ("TimeZone", "UTC".to_string()),
+                ("search_path", "app, public".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn statement_numbers_parameters_in_pairs() {
+        assert_eq!(statement(1), "SELECT set_config($1, $2, false)");
+        assert_eq!(
+            statement(3),
+            "SELECT set_config($1, $2, false), set_config($3, $4, false), set_config($5, $6, false)"
+        );
+    }
+}
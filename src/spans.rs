@@ -0,0 +1,160 @@
+//! Tracing spans around initialization, connection attempts, acquisition and health checks
+//!
+//! Without the `tracing` feature every function here simply awaits the wrapped future.
+
+#[cfg(feature = "tracing")]
+pub(crate) use enabled::{Address, init, instrument, record_writer};
+#[cfg(not(feature = "tracing"))]
+pub(crate) use disabled::{init, instrument, record_writer};
+
+/// Operation on a single pool that gets its own span
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum Operation {
+    /// A single attempt at opening a connection
+    Connect,
+    /// Checking a connection out of the pool
+    Acquire,
+    /// Running a health check against the pool
+    HealthCheck,
+}
+
+#[cfg(feature = "tracing")]
+mod enabled {
+    use super::Operation;
+    use crate::stats::PoolCounters;
+    use sqlx::postgres::PgConnectOptions;
+    use std::fmt::Display;
+    use std::future::Future;
+    use tracing::{Instrument, Span, field};
+
+    /// Where a pool connects to, split into the fields spans carry
+    #[derive(Debug)]
+    pub(crate) struct Address {
+        pub host: String,
+        pub port: u16,
+        pub database: Option<String>,
+    }
+
+    impl From<&PgConnectOptions> for Address {
+        fn from(options: &PgConnectOptions) -> Self {
+            Self {
+                host: options.get_host().to_string(),
+                port: options.get_port(),
+                database: options.get_database().map(str::to_string),
+            }
+        }
+    }
+
+    /// Open a span for an operation on a pool, carrying OpenTelemetry database fields
+    macro_rules! pool_span {
+        ($name:literal, $counters:expr) => {{
+            let counters = $counters;
+            tracing::info_span!(
+                $name,
+                db.system = "postgresql",
+                db.name = counters.address.database.as_deref(),
+                server.address = %counters.address.host,
+                server.port = counters.address.port,
+                role = counters.role.as_str(),
+                otel.status_code = field::Empty,
+                otel.status_message = field::Empty,
+            )
+        }};
+    }
+
+    /// Run `future` inside a span for `operation` on the pool, recording any error on the span
+    pub(crate) async fn instrument<T, E, F>(operation: Operation, counters: &PoolCounters, future: F) -> Result<T, E>
+    where
+        E: Display,
+        F: Future<Output = Result<T, E>>,
+    {
+        let span = match operation {
+            Operation::Connect => pool_span!("database.connect", counters),
+            Operation::Acquire => pool_span!("database.acquire", counters),
+            Operation::HealthCheck => pool_span!("database.health_check", counters),
+        };
+
+        let result = future.instrument(span.clone()).await;
+
+        if let Err(error) = &result {
+            record_error(&span, error);
+        }
+
+        result
+    }
+
+    /// Run `future` inside the initialization span, recording any error on the span
+    pub(crate) async fn init<T, E, F>(future: F) -> Result<T, E>
+    where
+        E: Display,
+        F: Future<Output = Result<T, E>>,
+    {
+        let span = tracing::info_span!(
+            "database.init",
+            db.system = "postgresql",
+            db.name = field::Empty,
+            server.address = field::Empty,
+            server.port = field::Empty,
+            otel.status_code = field::Empty,
+            otel.status_message = field::Empty,
+        );
+
+        let result = future.instrument(span.clone()).await;
+
+        // The failing connection attempt has already emitted the error event
+        if let Err(error) = &result {
+            record_status(&span, error);
+        }
+
+        result
+    }
+
+    /// Record the writer's address on the current initialization span
+    pub(crate) fn record_writer(options: &PgConnectOptions) {
+        let span = Span::current();
+
+        if let Some(database) = options.get_database() {
+            span.record("db.name", database);
+        }
+
+        span.record("server.address", options.get_host());
+        span.record("server.port", options.get_port());
+    }
+
+    /// Mark a span as failed and attach the error to it
+    fn record_error(span: &Span, error: &dyn Display) {
+        record_status(span, error);
+
+        tracing::error!(parent: span, error = %error, "database operation failed");
+    }
+
+    /// Mark a span as failed
+    fn record_status(span: &Span, error: &dyn Display) {
+        span.record("otel.status_code", "ERROR");
+        span.record("otel.status_message", field::display(error));
+    }
+}
+
+#[cfg(not(feature = "tracing"))]
+mod disabled {
+    use super::Operation;
+    use crate::stats::PoolCounters;
+    use sqlx::postgres::PgConnectOptions;
+    use std::future::Future;
+
+    pub(crate) async fn instrument<T, E, F>(_operation: Operation, _counters: &PoolCounters, future: F) -> Result<T, E>
+    where
+        F: Future<Output = Result<T, E>>,
+    {
+        future.await
+    }
+
+    pub(crate) async fn init<T, E, F>(future: F) -> Result<T, E>
+    where
+        F: Future<Output = Result<T, E>>,
+    {
+        future.await
+    }
+
+    pub(crate) fn record_writer(_options: &PgConnectOptions) {}
+}
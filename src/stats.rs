@@ -0,0 +1,191 @@
+//! Connection pool statistics snapshots
+
+use crate::Role;
+use crate::error::is_authentication;
+use crate::replica::endpoint;
+use crate::spans::{self, Operation};
+use sqlx::pool::PoolConnection;
+use sqlx::postgres::PgConnectOptions;
+use sqlx::{Pool, Postgres};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+use tokio::sync::Notify;
+
+/// Statistics of every pool, as returned by [`stats`](crate::stats)
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct DatabaseStats {
+    /// Statistics of the writer pool
+    pub writer: PoolStats,
+    /// Statistics of each replica pool, empty when reads use the writer
+    pub readers: Vec<PoolStats>,
+}
+
+/// Point-in-time statistics of a single connection pool
+///
+/// Acquire counters only cover connections checked out through
+/// [`Database::acquire_writer`](crate::Database::acquire_writer) and
+/// [`Database::acquire_reader`](crate::Database::acquire_reader), since sqlx
+/// does not report waits on queries run directly against a pool.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct PoolStats {
+    /// Role the pool serves
+    pub role: Role,
+    /// Pool address in `host:port/database` form
+    pub endpoint: String,
+    /// Number of open connections
+    pub size: u32,
+    /// Number of idle connections
+    pub idle: usize,
+    /// Configured maximum number of connections
+    pub max_connections: u32,
+    /// Configured minimum number of connections
+    pub min_connections: u32,
+    /// Number of connections successfully acquired
+    pub acquires: u64,
+    /// Number of acquire attempts that timed out
+    pub acquire_timeouts: u64,
+    /// Total time spent waiting for acquired connections
+    pub acquire_wait_total: Duration,
+    /// Longest time spent waiting for a single connection
+    pub acquire_wait_max: Duration,
+    /// Number of connections opened since the pool was created
+    pub connections_opened: u64,
+    /// Number of connections closed since the pool was created
+    pub connections_closed: u64,
+}
+
+impl PoolStats {
+    /// Number of connections currently checked out
+    pub fn in_use(&self) -> u32 {
+        self.size.saturating_sub(self.idle as u32)
+    }
+
+    /// Average time spent waiting for a connection
+    ///
+    /// # Returns
+    /// `None` if no connection has been acquired yet
+    pub fn acquire_wait_mean(&self) -> Option<Duration> {
+        let acquires = u32::try_from(self.acquires).unwrap_or(u32::MAX);
+        (acquires > 0).then(|| self.acquire_wait_total / acquires)
+    }
+}
+
+/// Default time an acquire may wait before it is logged as slow, matching sqlx's own default
+const DEFAULT_SLOW_ACQUIRE_THRESHOLD: Duration = Duration::from_secs(2);
+
+/// Running counters of a single pool, shared with its connection hooks
+#[derive(Debug)]
+pub(crate) struct PoolCounters {
+    /// Role the pool serves
+    pub role: Role,
+    /// Pool address in `host:port/database` form
+    pub endpoint: String,
+    /// Pool address split into the fields tracing spans carry
+    #[cfg(feature = "tracing")]
+    pub address: spans::Address,
+    /// Notified when the pool's credentials should be fetched again
+    pub refresh: Notify,
+    /// Acquires waiting longer than this are logged with the pool's role
+    slow_acquire_threshold: Duration,
+    opened: AtomicU64,
+    acquires: AtomicU64,
+    acquire_timeouts: AtomicU64,
+    // Wait times in microseconds
+    acquire_wait_total: AtomicU64,
+    acquire_wait_max: AtomicU64,
+}
+
+impl PoolCounters {
+    pub fn new(role: Role, options: &PgConnectOptions, slow_acquire_threshold: Option<Duration>) -> Self {
+        Self {
+            role,
+            endpoint: endpoint(options),
+            #[cfg(feature = "tracing")]
+            address: options.into(),
+            refresh: Notify::new(),
+            slow_acquire_threshold: slow_acquire_threshold.unwrap_or(DEFAULT_SLOW_ACQUIRE_THRESHOLD),
+            opened: AtomicU64::new(0),
+            acquires: AtomicU64::new(0),
+            acquire_timeouts: AtomicU64::new(0),
+            acquire_wait_total: AtomicU64::new(0),
+            acquire_wait_max: AtomicU64::new(0),
+        }
+    }
+
+    /// Record a newly opened connection
+    ///
+    /// Also asks for fresh credentials, so the next connection does not reuse these.
+    pub fn connected(&self) {
+        self.opened.fetch_add(1, Ordering::Relaxed);
+        self.refresh.notify_one();
+    }
+
+    /// Record a failed operation, asking for fresh credentials if the server rejected them
+    pub fn failed(&self, error: &sqlx::Error) {
+        if is_authentication(error) {
+            self.refresh.notify_one();
+        }
+    }
+
+    /// Acquire a connection from `pool`, recording how long it took
+    ///
+    /// Waits beyond the slow acquire threshold are logged with the role and
+    /// endpoint. This replaces sqlx's own slow acquire warning, which lacks both.
+    pub async fn acquire(&self, pool: &Pool<Postgres>) -> Result<PoolConnection<Postgres>, sqlx::Error> {
+        let started = Instant::now();
+        let result = spans::instrument(Operation::Acquire, self, pool.acquire()).await;
+
+        #[cfg(feature = "metrics")]
+        crate::telemetry::acquired(self, &result, started.elapsed());
+
+        match &result {
+            Ok(_) => {
+                let elapsed = started.elapsed();
+                let waited = u64::try_from(elapsed.as_micros()).unwrap_or(u64::MAX);
+
+                if elapsed > self.slow_acquire_threshold {
+                    log::warn!(
+                        "Slow {} database acquire from {}: waited {elapsed:?} for a connection",
+                        self.role,
+                        self.endpoint,
+                    );
+                }
+
+                self.acquires.fetch_add(1, Ordering::Relaxed);
+                self.acquire_wait_total.fetch_add(waited, Ordering::Relaxed);
+                self.acquire_wait_max.fetch_max(waited, Ordering::Relaxed);
+            }
+            Err(sqlx::Error::PoolTimedOut) => {
+                self.acquire_timeouts.fetch_add(1, Ordering::Relaxed);
+            }
+            Err(error) => self.failed(error),
+        }
+
+        result
+    }
+
+    /// Take a snapshot of the counters together with the pool's current state
+    pub fn snapshot(&self, pool: &Pool<Postgres>) -> PoolStats {
+        let options = pool.options();
+        let size = pool.size();
+        let opened = self.opened.load(Ordering::Relaxed);
+
+        PoolStats {
+            role: self.role,
+            endpoint: self.endpoint.clone(),
+            size,
+            idle: pool.num_idle(),
+            max_connections: options.get_max_connections(),
+            min_connections: options.get_min_connections(),
+            acquires: self.acquires.load(Ordering::Relaxed),
+            acquire_timeouts: self.acquire_timeouts.load(Ordering::Relaxed),
+            acquire_wait_total: Duration::from_micros(self.acquire_wait_total.load(Ordering::Relaxed)),
+            acquire_wait_max: Duration::from_micros(self.acquire_wait_max.load(Ordering::Relaxed)),
+            connections_opened: opened,
+            // Every connection ever opened is either still in the pool or was closed
+            connections_closed: opened.saturating_sub(u64::from(size)),
+        }
+    }
+}
@@ -0,0 +1,114 @@
+//! Pool metrics published through the `metrics` facade
+
+use crate::Role;
+use crate::replica::Replicas;
+use crate::stats::PoolCounters;
+use metrics::{Unit, counter, describe_counter, describe_gauge, describe_histogram, gauge, histogram};
+use sqlx::pool::PoolConnection;
+use sqlx::{Pool, Postgres};
+use std::sync::{Arc, Once};
+use std::time::Duration;
+use tokio::task::AbortHandle;
+use tokio::time::MissedTickBehavior;
+
+/// Time between samples of the pool gauges
+const SAMPLE_INTERVAL: Duration = Duration::from_secs(5);
+
+const POOL_CONNECTIONS: &str = "database_pool_connections";
+const POOL_IDLE_CONNECTIONS: &str = "database_pool_idle_connections";
+const ACQUIRE_DURATION: &str = "database_pool_acquire_duration_seconds";
+const ACQUIRE_TIMEOUTS: &str = "database_pool_acquire_timeouts_total";
+const CONNECTION_ERRORS: &str = "database_connection_errors_total";
+const REPLICA_LAG: &str = "database_replica_lag_seconds";
+
+/// Register descriptions of every metric with the installed recorder
+fn describe() {
+    static DESCRIBED: Once = Once::new();
+
+    DESCRIBED.call_once(|| {
+        describe_gauge!(POOL_CONNECTIONS, "Number of open connections in the pool");
+        describe_gauge!(POOL_IDLE_CONNECTIONS, "Number of idle connections in the pool");
+        describe_histogram!(ACQUIRE_DURATION, Unit::Seconds, "Time spent waiting for a pooled connection");
+        describe_counter!(ACQUIRE_TIMEOUTS, "Number of pool acquire attempts that timed out");
+        describe_counter!(CONNECTION_ERRORS, "Number of failed attempts to open or acquire a connection");
+        describe_gauge!(REPLICA_LAG, Unit::Seconds, "Replication lag measured by the last health check");
+    });
+}
+
+/// Labels identifying a pool
+fn labels(role: Role, endpoint: &str) -> [(&'static str, String); 2] {
+    [("role", role.as_str().to_string()), ("endpoint", endpoint.to_string())]
+}
+
+/// Record the outcome of an instrumented acquire
+pub(crate) fn acquired(
+    counters: &PoolCounters,
+    result: &Result<PoolConnection<Postgres>, sqlx::Error>,
+    waited: Duration,
+) {
+    let labels = labels(counters.role, &counters.endpoint);
+
+    match result {
+        Ok(_) => histogram!(ACQUIRE_DURATION, &labels).record(waited),
+        Err(sqlx::Error::PoolTimedOut) => counter!(ACQUIRE_TIMEOUTS, &labels).increment(1),
+        Err(_) => counter!(CONNECTION_ERRORS, &labels).increment(1),
+    }
+}
+
+/// Record a failed attempt to open a connection
+pub(crate) fn connection_failed(counters: &PoolCounters) {
+    // May run before `spawn()` when the initial connection fails
+    describe();
+
+    counter!(CONNECTION_ERRORS, &labels(counters.role, &counters.endpoint)).increment(1);
+}
+
+/// Spawn a task that samples pool sizes and replica lag into gauges
+///
+/// Like the health checker, the task holds only a weak reference to the
+/// replicas and stops once the [`Database`](crate::Database) is dropped,
+/// or when aborted through the returned handle.
+pub(crate) fn spawn(
+    writer: &Pool<Postgres>,
+    writer_counters: &Arc<PoolCounters>,
+    replicas: &Arc<Replicas>,
+) -> AbortHandle {
+    describe();
+
+    let writer = writer.clone();
+    let writer_counters = Arc::clone(writer_counters);
+    let replicas = Arc::downgrade(replicas);
+
+    let task = tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(SAMPLE_INTERVAL);
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+        loop {
+            ticker.tick().await;
+
+            let Some(replicas) = replicas.upgrade() else {
+                break;
+            };
+
+            sample(&writer, &writer_counters);
+
+            for replica in replicas.iter() {
+                sample(&replica.pool, &replica.counters);
+
+                if let Some(lag) = replica.lag() {
+                    gauge!(REPLICA_LAG, &labels(Role::Reader, &replica.endpoint)).set(lag);
+                }
+            }
+        }
+    });
+
+    task.abort_handle()
+}
+
+/// Publish the current size of a pool
+fn sample(pool: &Pool<Postgres>, counters: &PoolCounters) {
+    let labels = labels(counters.role, &counters.endpoint);
+
+    gauge!(POOL_CONNECTIONS, &labels).set(pool.size());
+    gauge!(POOL_IDLE_CONNECTIONS, &labels).set(pool.num_idle() as f64);
+}
@@ -0,0 +1,313 @@
+//! TLS settings for the connections of a pool
+
+use crate::config::Env;
+use crate::{DatabaseError, Role};
+use sqlx::postgres::{PgConnectOptions, PgSslMode};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::{fmt, fs, io};
+
+/// How strictly a pool requires and verifies TLS, mirroring libpq's `sslmode`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum SslMode {
+    /// Never use TLS
+    Disable,
+    /// Try without TLS first, falling back to TLS
+    Allow,
+    /// Try TLS first, falling back to an unencrypted connection
+    #[default]
+    Prefer,
+    /// Always use TLS without verifying the server certificate
+    ///
+    /// As in libpq, giving a root certificate in [`TlsConfig::root_cert`] turns
+    /// this into [`SslMode::VerifyCa`].
+    Require,
+    /// Always use TLS and verify the server certificate against the root certificate
+    VerifyCa,
+    /// Like `VerifyCa`, and also check that the certificate matches the host name
+    VerifyFull,
+}
+
+impl SslMode {
+    /// Name of the mode as accepted by `DATABASE_SSL_MODE` and libpq
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Disable => "disable",
+            Self::Allow => "allow",
+            Self::Prefer => "prefer",
+            Self::Require => "require",
+            Self::VerifyCa => "verify-ca",
+            Self::VerifyFull => "verify-full",
+        }
+    }
+}
+
+impl fmt::Display for SslMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for SslMode {
+    type Err = String;
+
+    fn from_str(string: &str) -> Result<Self, Self::Err> {
+        match string.trim().to_ascii_lowercase().replace('_', "-").as_str() {
+            "disable" => Ok(Self::Disable),
+            "allow" => Ok(Self::Allow),
+            "prefer" => Ok(Self::Prefer),
+            "require" => Ok(Self::Require),
+            "verify-ca" => Ok(Self::VerifyCa),
+            "verify-full" => Ok(Self::VerifyFull),
+            _ => Err(format!("unknown ssl mode: {string}")),
+        }
+    }
+}
+
+impl From<SslMode> for PgSslMode {
+    fn from(mode: SslMode) -> Self {
+        match mode {
+            SslMode::Disable => Self::Disable,
+            SslMode::Allow => Self::Allow,
+            SslMode::Prefer => Self::Prefer,
+            SslMode::Require => Self::Require,
+            SslMode::VerifyCa => Self::VerifyCa,
+            SslMode::VerifyFull => Self::VerifyFull,
+        }
+    }
+}
+
+/// TLS settings applied to every connection of a pool
+///
+/// Any field left as `None` keeps what the connection string specifies.
+/// Encrypted connections need the `tls-rustls` or `tls-native-tls` feature.
+/// Certificates are loaded when the pool is built, so a missing or malformed
+/// file fails initialization with [`DatabaseError::Certificate`].
+///
+/// # Example
+/// ```
+/// use database::{PoolConfig, SslMode, TlsConfig};
+///
+/// let config = PoolConfig {
+///     tls: TlsConfig {
+///         mode: Some(SslMode::VerifyFull),
+///         root_cert: Some("/etc/ssl/certs/rds-ca.pem".into()),
+///         ..TlsConfig::default()
+///     },
+///     ..PoolConfig::default()
+/// };
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TlsConfig {
+    /// How strictly TLS is required and verified
+    pub mode: Option<SslMode>,
+    /// PEM file with the certificate authorities the server certificate is verified against
+    pub root_cert: Option<PathBuf>,
+    /// PEM file with the client certificate presented to the server
+    pub client_cert: Option<PathBuf>,
+    /// PEM file with the private key of the client certificate
+    pub client_key: Option<PathBuf>,
+}
+
+impl TlsConfig {
+    /// Read the TLS settings for a role from environment variables
+    ///
+    /// Like the pool settings, each variable may be given per role, e.g.
+    /// `DATABASE_READ_SSL_ROOT_CERT`, falling back to the shared name.
+    ///
+    /// # Environment Variables
+    /// - SSL_MODE: `disable`, `allow`, `prefer`, `require`, `verify-ca` or `verify-full`
+    /// - SSL_ROOT_CERT: Path of the CA certificate file
+    /// - SSL_CLIENT_CERT: Path of the client certificate file
+    /// - SSL_CLIENT_KEY: Path of the client private key file
+    ///
+    /// # Errors
+    /// [`DatabaseError::InvalidConfiguration`] if the mode cannot be parsed
+    pub fn from_env(role: Role) -> Result<Self, DatabaseError> {
+        Self::read(&Env::default(), role)
+    }
+
+    /// Read the TLS settings for a role from variables under the prefix of `env`
+    pub(crate) fn read(env: &Env, role: Role) -> Result<Self, DatabaseError> {
+        let path = |key| env.role_var(role, key).map(|(_, path)| PathBuf::from(path));
+
+        Ok(Self {
+            mode: env.parse_var(role, "SSL_MODE")?,
+            root_cert: path("SSL_ROOT_CERT"),
+            client_cert: path("SSL_CLIENT_CERT"),
+            client_key: path("SSL_CLIENT_KEY"),
+        })
+    }
+
+    /// Load the certificates and apply the settings to the connect options
+    ///
+    /// # Errors
+    /// [`DatabaseError::Certificate`] if a file cannot be read, holds no PEM data,
+    /// or a client certificate is given without its key or the other way around
+    pub(crate) fn apply(&self, role: Role, mut options: PgConnectOptions) -> Result<PgConnectOptions, DatabaseError> {
+        if let Some(mode) = self.mode {
+            options = options.ssl_mode(mode.into());
+        }
+
+        if let Some(path) = &self.root_cert {
+            options = options.ssl_root_cert_from_pem(load(role, path)?);
+
+            // sqlx accepts any server certificate in `require` mode, root certificate or not
+            if matches!(options.get_ssl_mode(), PgSslMode::Require) {
+                options = options.ssl_mode(PgSslMode::VerifyCa);
+            }
+        }
+
+        match (&self.client_cert, &self.client_key) {
+            (Some(cert), Some(key)) => {
+                options = options
+                    .ssl_client_cert_from_pem(load(role, cert)?)
+                    .ssl_client_key_from_pem(load(role, key)?);
+            }
+            (Some(path), None) | (None, Some(path)) => {
+                return Err(DatabaseError::Certificate {
+                    role,
+                    path: path.clone(),
+                    source: io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        "a client certificate and its key must be given together",
+                    ),
+                });
+            }
+            (None, None) => {}
+        }
+
+        Ok(options)
+    }
+}
+
+/// Read a PEM file, rejecting files that do not contain any PEM block
+fn load(role: Role, path: &Path) -> Result<Vec<u8>, DatabaseError> {
+    let failed = |source| DatabaseError::Certificate { role, path: path.to_path_buf(), source };
+    let contents = fs::read(path).map_err(failed)?;
+
+    if !contents.windows(11).any(|window| window == b"-----BEGIN ") {
+        return Err(failed(io::Error::new(io::ErrorKind::InvalidData, "no PEM data found")));
+    }
+
+    Ok(contents)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PEM: &str = "-----BEGIN CERTIFICATE-----\nMIIB\n-----END CERTIFICATE-----\n";
+
+    fn scratch(name: &str, contents: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("database-tls-{}-{name}", std::process::id()));
+        fs::write(&path, contents).unwrap();
+        path
+    }
+
+    fn apply(config: &TlsConfig) -> Result<PgConnectOptions, DatabaseError> {
+        config.apply(Role::Writer, PgConnectOptions::new())
+    }
+
+    #[test]
+    fn client_cert_and_key_must_be_given_together() {
+        let cert = scratch("client-cert.pem", PEM);
+        let key = scratch("client-key.pem", PEM);
+
+        let without_key = apply(&TlsConfig {
+            client_cert: Some(cert.clone()),
+            ..TlsConfig::default()
+        });
+        let without_cert = apply(&TlsConfig {
+            client_key: Some(key.clone()),
+            ..TlsConfig::default()
+        });
+        let both = apply(&TlsConfig {
+            client_cert: Some(cert.clone()),
+            client_key: Some(key.clone()),
+            ..TlsConfig::default()
+        });
+        let _ = fs::remove_file(&cert);
+        let _ = fs::remove_file(&key);
+
+        assert!(
+            matches!(without_key, Err(DatabaseError::Certificate { path, .. }) if path == cert)
+        );
+        assert!(
+            matches!(without_cert, Err(DatabaseError::Certificate { path, .. }) if path == key)
+        );
+        assert!(both.is_ok());
+    }
+
+    #[test]
+    fn files_without_pem_data_are_rejected() {
+        let root = scratch("not-pem.pem", "just some text\n");
+
+        let result = apply(&TlsConfig {
+            root_cert: Some(root.clone()),
+            ..TlsConfig::default()
+        });
+        let _ = fs::remove_file(&root);
+
+        assert!(matches!(
+            result,
+            Err(DatabaseError::Certificate { ref source, .. }) if source.kind() == io::ErrorKind::InvalidData
+        ));
+    }
+
+    #[test]
+    fn missing_files_are_rejected() {
+        let root =
+            std::env::temp_dir().join(format!("database-tls-{}-missing.pem", std::process::id()));
+
+        let result = apply(&TlsConfig {
+            root_cert: Some(root),
+            ..TlsConfig::default()
+        });
+
+        assert!(
+            matches!(result, Err(DatabaseError::Certificate { ref source, .. }) if source.kind() == io::ErrorKind::NotFound)
+        );
+    }
+
+    #[test]
+    fn require_with_root_cert_verifies_the_server() {
+        let root = scratch("root.pem", PEM);
+
+        let with_root = apply(&TlsConfig {
+            mode: Some(SslMode::Require),
+            root_cert: Some(root.clone()),
+            ..TlsConfig::default()
+        });
+        let without_root = apply(&TlsConfig {
+            mode: Some(SslMode::Require),
+            ..TlsConfig::default()
+        });
+        let full = apply(&TlsConfig {
+            mode: Some(SslMode::VerifyFull),
+            root_cert: Some(root.clone()),
+            ..TlsConfig::default()
+        });
+        let _ = fs::remove_file(&root);
+
+        assert!(matches!(
+            with_root.unwrap().get_ssl_mode(),
+            PgSslMode::VerifyCa
+        ));
+        assert!(matches!(
+            without_root.unwrap().get_ssl_mode(),
+            PgSslMode::Require
+        ));
+        assert!(matches!(
+            full.unwrap().get_ssl_mode(),
+            PgSslMode::VerifyFull
+        ));
+    }
+
+    #[test]
+    fn ssl_modes_parse_like_libpq() {
+        assert_eq!("verify-full".parse(), Ok(SslMode::VerifyFull));
+        assert_eq!(" Verify_CA ".parse(), Ok(SslMode::VerifyCa));
+        assert!("strict".parse::<SslMode>().is_err());
+    }
+}